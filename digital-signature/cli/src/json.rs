@@ -0,0 +1,210 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! JSON encodings for other tools to consume. Digests, nonces and salts are written as hex
+//! strings, rather than the arrays of numbers their own serde implementations give.
+
+use risc0_zkp::core::sha::Digest;
+use serde::{Deserialize, Serialize};
+
+use crate::{KeyDerivation, Message, SignMessageCommit, SigningContext};
+
+mod hex_digest {
+    use risc0_zkp::core::sha::Digest;
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    use crate::{format_identity, parse_identity};
+
+    pub fn serialize<S: Serializer>(digest: &Digest, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format_identity(digest))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Digest, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        parse_identity(&s).map_err(de::Error::custom)
+    }
+}
+
+mod hex_bytes {
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8; 32], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&hex::encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<[u8; 32], D::Error> {
+        let s = String::deserialize(deserializer)?;
+        let bytes = hex::decode(s).map_err(de::Error::custom)?;
+        bytes
+            .try_into()
+            .map_err(|bytes: Vec<u8>| de::Error::invalid_length(bytes.len(), &"32 bytes"))
+    }
+}
+
+mod hex_message {
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    use crate::Message;
+
+    pub fn serialize<S: Serializer>(msg: &Message, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(msg)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Message, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(de::Error::custom)
+    }
+}
+
+/// [KeyDerivation], tagged with the name of its scheme.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(tag = "scheme", rename_all = "snake_case")]
+pub enum HexDerivation {
+    Legacy,
+    Stretched {
+        #[serde(with = "hex_bytes")]
+        salt: [u8; 32],
+        iterations: u32,
+    },
+}
+
+impl From<KeyDerivation> for HexDerivation {
+    fn from(derivation: KeyDerivation) -> Self {
+        match derivation {
+            KeyDerivation::Legacy => HexDerivation::Legacy,
+            KeyDerivation::Stretched { salt, iterations } => {
+                HexDerivation::Stretched { salt, iterations }
+            }
+        }
+    }
+}
+
+impl From<HexDerivation> for KeyDerivation {
+    fn from(derivation: HexDerivation) -> Self {
+        match derivation {
+            HexDerivation::Legacy => KeyDerivation::Legacy,
+            HexDerivation::Stretched { salt, iterations } => {
+                KeyDerivation::Stretched { salt, iterations }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct HexContext {
+    pub domain: String,
+    #[serde(with = "hex_bytes")]
+    pub nonce: [u8; 32],
+    pub timestamp: u64,
+}
+
+impl From<SigningContext> for HexContext {
+    fn from(context: SigningContext) -> Self {
+        HexContext {
+            domain: context.domain,
+            nonce: context.nonce,
+            timestamp: context.timestamp,
+        }
+    }
+}
+
+impl From<HexContext> for SigningContext {
+    fn from(context: HexContext) -> Self {
+        SigningContext {
+            domain: context.domain,
+            nonce: context.nonce,
+            timestamp: context.timestamp,
+        }
+    }
+}
+
+/// [SignMessageCommit] in a form suited to JSON. The commit's own serde implementation has to
+/// match the journal layout, so it can't be changed to write hex.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct HexCommit {
+    #[serde(with = "hex_digest")]
+    pub identity: Digest,
+    pub derivation: HexDerivation,
+    pub context: HexContext,
+    #[serde(with = "hex_message")]
+    pub msg: Message,
+}
+
+impl From<SignMessageCommit> for HexCommit {
+    fn from(commit: SignMessageCommit) -> Self {
+        HexCommit {
+            identity: commit.identity,
+            derivation: commit.derivation.into(),
+            context: commit.context.into(),
+            msg: commit.msg,
+        }
+    }
+}
+
+impl From<HexCommit> for SignMessageCommit {
+    fn from(commit: HexCommit) -> Self {
+        SignMessageCommit {
+            identity: commit.identity,
+            derivation: commit.derivation.into(),
+            context: commit.context.into(),
+            msg: commit.msg,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stretched;
+
+    fn commit() -> SignMessageCommit {
+        SignMessageCommit {
+            identity: Digest::new([0x01020304; 8]),
+            derivation: stretched("salt", 16),
+            context: SigningContext {
+                domain: "test".to_string(),
+                nonce: [0xab; 32],
+                timestamp: 1_700_000_000,
+            },
+            msg: Message { msg: [0x5a; 32] },
+        }
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let json = serde_json::to_value(HexCommit::from(commit())).unwrap();
+        assert_eq!(json["identity"].as_str().unwrap().len(), 64);
+        assert_eq!(json["msg"], "5a".repeat(32));
+        assert_eq!(json["context"]["nonce"], "ab".repeat(32));
+        assert_eq!(json["derivation"]["scheme"], "stretched");
+        assert_eq!(json["derivation"]["iterations"], 16);
+
+        let parsed: HexCommit = serde_json::from_value(json).unwrap();
+        assert_eq!(SignMessageCommit::from(parsed), commit());
+
+        let legacy = serde_json::to_value(HexDerivation::Legacy).unwrap();
+        assert_eq!(legacy, serde_json::json!({ "scheme": "legacy" }));
+    }
+
+    #[test]
+    fn rejects_malformed_hex() {
+        let mut json = serde_json::to_value(HexCommit::from(commit())).unwrap();
+        json["msg"] = "5a".into();
+        assert!(serde_json::from_value::<HexCommit>(json).is_err());
+
+        let mut json = serde_json::to_value(HexCommit::from(commit())).unwrap();
+        json["context"]["nonce"] = "zz".repeat(32).into();
+        assert!(serde_json::from_value::<HexCommit>(json).is_err());
+    }
+}
@@ -0,0 +1,167 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::str::FromStr;
+use std::{fmt, fs, io};
+
+use anyhow::{anyhow, bail, Context};
+use risc0_zkp::core::sha::Digest;
+use risc0_zkvm::Result;
+
+use crate::{format_identity, parse_identity};
+
+/// Local trust store mapping names to the identities a verifier knows about.
+///
+/// On disk a keyring is a text file with one `name identity` pair per line, where the identity
+/// is the hex digest printed by `sign`. Blank lines and lines starting with `#` are ignored.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Keyring {
+    identities: BTreeMap<String, Digest>,
+}
+
+impl Keyring {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Read a keyring from `path`. A missing file is read as an empty keyring.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        match fs::read_to_string(path) {
+            Ok(contents) => contents
+                .parse()
+                .with_context(|| format!("failed to read keyring {}", path.display())),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Self::new()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        fs::write(path, self.to_string())?;
+        Ok(())
+    }
+
+    /// Add a named identity. Names and identities must both be unique within the keyring.
+    pub fn add(&mut self, name: &str, identity: Digest) -> Result<()> {
+        if name.is_empty() || name.contains(char::is_whitespace) || name.starts_with('#') {
+            bail!("invalid identity name {:?}", name);
+        }
+        if self.identities.contains_key(name) {
+            bail!("{} is already in the keyring", name);
+        }
+        if let Some(existing) = self.name_of(&identity) {
+            bail!("identity is already in the keyring as {}", existing);
+        }
+        self.identities.insert(name.to_string(), identity);
+        Ok(())
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<Digest> {
+        self.identities.remove(name)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Digest> {
+        self.identities.get(name)
+    }
+
+    /// Name under which `identity` is known, if any.
+    pub fn name_of(&self, identity: &Digest) -> Option<&str> {
+        self.identities
+            .iter()
+            .find(|(_, known)| *known == identity)
+            .map(|(name, _)| name.as_str())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Digest)> {
+        self.identities
+            .iter()
+            .map(|(name, identity)| (name.as_str(), identity))
+    }
+
+    pub fn len(&self) -> usize {
+        self.identities.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.identities.is_empty()
+    }
+}
+
+impl FromStr for Keyring {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut keyring = Keyring::new();
+        for (number, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let parsed = line
+                .split_once(char::is_whitespace)
+                .ok_or_else(|| anyhow!("expected a name and an identity"))
+                .and_then(|(name, identity)| keyring.add(name, parse_identity(identity)?));
+            parsed.with_context(|| format!("line {}", number + 1))?;
+        }
+        Ok(keyring)
+    }
+}
+
+impl fmt::Display for Keyring {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, identity) in self.iter() {
+            writeln!(f, "{} {}", name, format_identity(identity))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn identity(word: u32) -> Digest {
+        Digest::new([word; 8])
+    }
+
+    #[test]
+    fn text_round_trip() {
+        let mut keyring = Keyring::new();
+        keyring.add("alice", identity(1)).unwrap();
+        keyring.add("bob", identity(2)).unwrap();
+        let parsed: Keyring = keyring.to_string().parse().unwrap();
+        assert_eq!(parsed, keyring);
+        assert_eq!(parsed.name_of(&identity(2)), Some("bob"));
+        assert_eq!(parsed.name_of(&identity(3)), None);
+
+        let commented = format!("# trusted signers\n\n{}", keyring);
+        assert_eq!(commented.parse::<Keyring>().unwrap(), keyring);
+    }
+
+    #[test]
+    fn rejects_duplicates() {
+        let mut keyring = Keyring::new();
+        keyring.add("alice", identity(1)).unwrap();
+        assert!(keyring.add("alice", identity(2)).is_err());
+        assert!(keyring.add("alias", identity(1)).is_err());
+        assert!(keyring.add("two words", identity(3)).is_err());
+
+        let text = format!("alice {0}\nalias {0}\n", format_identity(&identity(1)));
+        assert!(text.parse::<Keyring>().is_err());
+        assert!("alice".parse::<Keyring>().is_err());
+        assert!("alice 1234".parse::<Keyring>().is_err());
+    }
+}
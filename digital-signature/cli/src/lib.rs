@@ -12,14 +12,79 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-pub use digital_signature_core::{Message, Passphrase, SignMessageCommit, SigningRequest};
-use digital_signature_methods::{SIGN_ELF, SIGN_ID};
+pub mod json;
+pub mod keyring;
+pub mod nonces;
+pub mod revocation;
+pub mod signature_file;
+
+use std::io::{self, Read};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Context};
+pub use digital_signature_core::batch::{BatchSigningRequest, InclusionProof, SignBatchCommit};
+pub use digital_signature_core::threshold::{
+    SignThresholdCommit, Signer, SignerSet, ThresholdSigningRequest,
+};
+pub use digital_signature_core::{
+    KeyDerivation, Message, ParseMessageError, Passphrase, PlaintextSigningRequest, RotateCommit,
+    RotationRequest, SignMessageCommit, SigningContext, SigningRequest, DEFAULT_ITERATIONS,
+};
+use digital_signature_methods::{
+    ROTATE_ELF, ROTATE_ID, SIGN_BATCH_ELF, SIGN_BATCH_ID, SIGN_ELF, SIGN_ID, SIGN_PLAINTEXT_ELF,
+    SIGN_PLAINTEXT_ID, SIGN_THRESHOLD_ELF, SIGN_THRESHOLD_ID,
+};
+use rand::RngCore;
 use risc0_zkvm::serde::{from_slice, to_vec};
 use risc0_zkvm::{Prover, Receipt, Result};
 use sha2::{Digest, Sha256};
 
+pub use crate::json::HexCommit;
+pub use crate::keyring::Keyring;
+pub use crate::nonces::{NonceStore, DEFAULT_RETENTION};
+pub use crate::revocation::{Revocation, RevocationList, RevokedIdentity};
+pub use crate::signature_file::{SignatureFile, SIGNATURE_FORMAT_VERSION};
+
+/// Where the message digest in a signature was computed.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum HashMode {
+    /// The host hashed the message, and the guest signed the digest it was handed.
+    Host,
+    /// The guest hashed the full message, so the signature binds the message content.
+    Guest,
+}
+
+impl HashMode {
+    /// ID of the method that signs messages hashed in this mode.
+    pub fn method_id(&self) -> &'static [u8] {
+        match self {
+            HashMode::Host => SIGN_ID,
+            HashMode::Guest => SIGN_PLAINTEXT_ID,
+        }
+    }
+}
+
 pub struct SignatureWithReceipt {
     receipt: Receipt,
+    mode: HashMode,
+}
+
+/// Who a signature has to come from to pass [SignatureWithReceipt::verify_all].
+#[derive(Clone, Copy, Debug)]
+pub enum ExpectedSigner<'a> {
+    /// Exactly this identity.
+    Identity(&'a risc0_zkp::core::sha::Digest),
+    /// Any identity named in the keyring.
+    Known(&'a Keyring),
+}
+
+/// Everything a verifier requires of a signature besides the message it signs.
+#[derive(Clone, Copy, Debug)]
+pub struct VerifyPolicy<'a> {
+    pub signer: ExpectedSigner<'a>,
+    pub revocations: &'a RevocationList,
+    pub domain: &'a str,
+    pub max_age: u64,
 }
 
 impl SignatureWithReceipt {
@@ -38,28 +103,248 @@ impl SignatureWithReceipt {
         Ok(commit.msg)
     }
 
+    pub fn get_mode(&self) -> HashMode {
+        self.mode
+    }
+
     pub fn verify(&self) -> Result<SignMessageCommit> {
-        self.receipt.verify(SIGN_ID)?;
+        self.receipt.verify(self.mode.method_id())?;
         self.get_commit()
     }
+
+    /// Verify the signature, and fail with a [RevokedIdentity] if it was made by an identity
+    /// that `revocations` lists as rotated or revoked.
+    pub fn verify_with(&self, revocations: &RevocationList) -> Result<SignMessageCommit> {
+        let commit = self.verify()?;
+        revocations.check(&commit.identity)?;
+        Ok(commit)
+    }
+
+    /// Verify the signature, check its context with [check_context], and record its nonce in
+    /// `nonces` so that it is not accepted again.
+    pub fn verify_fresh(
+        &self,
+        domain: &str,
+        max_age: u64,
+        nonces: &mut NonceStore,
+    ) -> Result<SignMessageCommit> {
+        let commit = self.verify()?;
+        check_fresh(&commit.context, domain, max_age, nonces)?;
+        Ok(commit)
+    }
+
+    fn verify_signs(&self, msg: &Message) -> Result<SignMessageCommit> {
+        let commit = self.verify()?;
+        if &commit.msg != msg {
+            bail!("signature is for a different message");
+        }
+        Ok(commit)
+    }
+
+    /// Verify the signature, and check that it signs `msg_str` on behalf of `identity`. The
+    /// context is not checked; [SignatureWithReceipt::verify_all] checks both.
+    pub fn verify_message(
+        &self,
+        msg_str: impl AsRef<[u8]>,
+        identity: &risc0_zkp::core::sha::Digest,
+    ) -> Result<SignMessageCommit> {
+        self.verify_digest(&hash_message(msg_str), identity)
+    }
+
+    /// Like [SignatureWithReceipt::verify_message], for a message already hashed, e.g. with
+    /// [hash_reader].
+    pub fn verify_digest(
+        &self,
+        msg: &Message,
+        identity: &risc0_zkp::core::sha::Digest,
+    ) -> Result<SignMessageCommit> {
+        let commit = self.verify_signs(msg)?;
+        if &commit.identity != identity {
+            bail!(
+                "signature was made by {}, not {}",
+                format_identity(&commit.identity),
+                format_identity(identity)
+            );
+        }
+        Ok(commit)
+    }
+
+    /// Verify the signature over `msg_str`, and look up the signer in `keyring`. Returns the name
+    /// the signer is known by, and fails if the signer is not in the keyring. The context is not
+    /// checked; [SignatureWithReceipt::verify_all] checks both.
+    pub fn verify_known(
+        &self,
+        msg_str: impl AsRef<[u8]>,
+        keyring: &Keyring,
+    ) -> Result<(String, SignMessageCommit)> {
+        self.verify_known_digest(&hash_message(msg_str), keyring)
+    }
+
+    /// Like [SignatureWithReceipt::verify_known], for a message already hashed.
+    pub fn verify_known_digest(
+        &self,
+        msg: &Message,
+        keyring: &Keyring,
+    ) -> Result<(String, SignMessageCommit)> {
+        let commit = self.verify_signs(msg)?;
+        match keyring.name_of(&commit.identity) {
+            Some(name) => Ok((name.to_string(), commit)),
+            None => bail!(
+                "signature was made by unknown identity {}",
+                format_identity(&commit.identity)
+            ),
+        }
+    }
+
+    /// Verify the signature and run every check `policy` asks for: that it signs `msg` on behalf
+    /// of the expected signer, that the signer's identity was not rotated or revoked, and that its
+    /// context passes [check_context] with a nonce not already in `nonces`. The nonce is only
+    /// recorded once all of that holds. Nonces past the store's retention window are pruned, and
+    /// `policy.max_age` may not exceed that window.
+    pub fn verify_all(
+        &self,
+        msg: &Message,
+        policy: &VerifyPolicy,
+        nonces: &mut NonceStore,
+    ) -> Result<SignMessageCommit> {
+        let commit = match policy.signer {
+            ExpectedSigner::Identity(identity) => self.verify_digest(msg, identity)?,
+            ExpectedSigner::Known(keyring) => self.verify_known_digest(msg, keyring)?.1,
+        };
+        policy.revocations.check(&commit.identity)?;
+        nonces.prune(now());
+        check_fresh(&commit.context, policy.domain, policy.max_age, nonces)?;
+        Ok(commit)
+    }
+
+    /// Encode the signature as a [SignatureFile].
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        SignatureFile::new(self).to_bytes()
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        SignatureFile::from_bytes(bytes)?.open()
+    }
+}
+
+/// How far ahead of the verifier's clock a signature's timestamp may be.
+pub const MAX_CLOCK_SKEW: u64 = 300;
+
+/// Current time in seconds since the UNIX epoch.
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system clock is before the UNIX epoch")
+        .as_secs()
+}
+
+/// Context for a new signature in `domain`, with a random nonce and the current time.
+pub fn fresh_context(domain: &str) -> SigningContext {
+    let mut nonce = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut nonce);
+    SigningContext {
+        domain: domain.to_string(),
+        nonce,
+        timestamp: now(),
+    }
+}
+
+/// Check that a signature's context is for `domain`, and that at time `now` it is at most
+/// `max_age` seconds old. Timestamps up to [MAX_CLOCK_SKEW] in the future are tolerated.
+pub fn check_context(context: &SigningContext, domain: &str, max_age: u64, now: u64) -> Result<()> {
+    if context.domain != domain {
+        bail!(
+            "signature is for domain {:?}, not {:?}",
+            context.domain,
+            domain
+        );
+    }
+    if context.timestamp > now.saturating_add(MAX_CLOCK_SKEW) {
+        bail!(
+            "signature is dated {}s in the future",
+            context.timestamp - now
+        );
+    }
+    let age = now.saturating_sub(context.timestamp);
+    if age > max_age {
+        bail!(
+            "signature is {}s old, more than the maximum of {}s",
+            age,
+            max_age
+        );
+    }
+    Ok(())
+}
+
+/// Check a context with [check_context] at the current time, and record its nonce in `nonces`.
+fn check_fresh(
+    context: &SigningContext,
+    domain: &str,
+    max_age: u64,
+    nonces: &mut NonceStore,
+) -> Result<()> {
+    nonces.check_max_age(max_age)?;
+    check_context(context, domain, max_age, now())?;
+    nonces.insert(&context.nonce, context.timestamp)
+}
+
+/// Hex encoding of an identity digest.
+pub fn format_identity(identity: &risc0_zkp::core::sha::Digest) -> String {
+    hex::encode(identity.as_bytes())
 }
 
-pub fn sign(pass_str: impl AsRef<[u8]>, msg_str: impl AsRef<[u8]>) -> Result<SignatureWithReceipt> {
+/// Parse an identity digest from the hex encoding written by [format_identity].
+pub fn parse_identity(hex_str: &str) -> Result<risc0_zkp::core::sha::Digest> {
+    let bytes = hex::decode(hex_str.trim()).context("identity is not valid hex")?;
+    if bytes.len() != 32 {
+        bail!("identity must be 32 bytes, not {}", bytes.len());
+    }
+    let mut words = [0u32; 8];
+    for (word, chunk) in words.iter_mut().zip(bytes.chunks_exact(4)) {
+        *word = u32::from_ne_bytes(chunk.try_into().unwrap());
+    }
+    Ok(risc0_zkp::core::sha::Digest::new(words))
+}
+
+/// Stretched key derivation with the SHA-256 of `salt_str` as the salt. Signers must reuse the
+/// same salt and iteration count to keep the same identity.
+pub fn stretched(salt_str: impl AsRef<[u8]>, iterations: u32) -> KeyDerivation {
+    let mut salt = [0u8; 32];
+    salt.copy_from_slice(&Sha256::digest(salt_str));
+    KeyDerivation::Stretched { salt, iterations }
+}
+
+fn hash_passphrase(pass_str: impl AsRef<[u8]>) -> Passphrase {
     let mut pass_hasher = Sha256::new();
     pass_hasher.update(pass_str);
     let mut pass_hash = [0u8; 32];
     pass_hash.copy_from_slice(&pass_hasher.finalize());
+    Passphrase { pass: pass_hash }
+}
 
-    let mut msg_hasher = Sha256::new();
-    msg_hasher.update(msg_str);
-    let mut msg_hash = [0u8; 32];
-    msg_hash.copy_from_slice(&msg_hasher.finalize());
+/// Sign the SHA-256 digest of a message, computed on the host.
+pub fn sign(
+    pass_str: impl AsRef<[u8]>,
+    derivation: &KeyDerivation,
+    context: &SigningContext,
+    msg_str: impl AsRef<[u8]>,
+) -> Result<SignatureWithReceipt> {
+    sign_digest(pass_str, derivation, context, hash_message(msg_str))
+}
 
-    let pass = Passphrase { pass: pass_hash };
-    let msg = Message { msg: msg_hash };
+/// Sign a message the host already hashed, e.g. with [hash_reader].
+pub fn sign_digest(
+    pass_str: impl AsRef<[u8]>,
+    derivation: &KeyDerivation,
+    context: &SigningContext,
+    msg: Message,
+) -> Result<SignatureWithReceipt> {
+    let pass = hash_passphrase(pass_str);
 
     let params = SigningRequest {
         passphrase: pass,
+        derivation: derivation.clone(),
+        context: context.clone(),
         msg: msg,
     };
 
@@ -67,7 +352,290 @@ pub fn sign(pass_str: impl AsRef<[u8]>, msg_str: impl AsRef<[u8]>) -> Result<Sig
     let vec = to_vec(&params).unwrap();
     prover.add_input_u32_slice(vec.as_slice());
     let receipt = prover.run()?;
-    Ok(SignatureWithReceipt { receipt })
+    Ok(SignatureWithReceipt {
+        receipt,
+        mode: HashMode::Host,
+    })
+}
+
+/// Sign a message by passing all of it to the guest, which hashes it inside the zkVM. The
+/// committed message is the same SHA-256 digest that [sign] commits, but here the receipt proves
+/// it was computed from the message bytes.
+pub fn sign_plaintext(
+    pass_str: impl AsRef<[u8]>,
+    derivation: &KeyDerivation,
+    context: &SigningContext,
+    msg_str: impl AsRef<[u8]>,
+) -> Result<SignatureWithReceipt> {
+    let params = PlaintextSigningRequest {
+        passphrase: hash_passphrase(pass_str),
+        derivation: derivation.clone(),
+        context: context.clone(),
+        msg: msg_str.as_ref().to_vec(),
+    };
+
+    let mut prover = Prover::new(SIGN_PLAINTEXT_ELF, SIGN_PLAINTEXT_ID)?;
+    let vec = to_vec(&params).unwrap();
+    prover.add_input_u32_slice(vec.as_slice());
+    let receipt = prover.run()?;
+    Ok(SignatureWithReceipt {
+        receipt,
+        mode: HashMode::Guest,
+    })
+}
+
+pub struct BatchSignatureWithReceipt {
+    receipt: Receipt,
+}
+
+impl BatchSignatureWithReceipt {
+    pub fn get_commit(&self) -> Result<SignBatchCommit> {
+        let msg = &self.receipt.journal;
+        Ok(from_slice(msg.as_slice()).unwrap())
+    }
+
+    pub fn get_identity(&self) -> Result<risc0_zkp::core::sha::Digest> {
+        let commit = self.get_commit()?;
+        Ok(commit.identity)
+    }
+
+    pub fn verify(&self) -> Result<SignBatchCommit> {
+        self.receipt.verify(SIGN_BATCH_ID)?;
+        self.get_commit()
+    }
+
+    /// Like [SignatureWithReceipt::verify_fresh], for the whole batch.
+    pub fn verify_fresh(
+        &self,
+        domain: &str,
+        max_age: u64,
+        nonces: &mut NonceStore,
+    ) -> Result<SignBatchCommit> {
+        let commit = self.verify()?;
+        check_fresh(&commit.context, domain, max_age, nonces)?;
+        Ok(commit)
+    }
+
+    /// Verify the batch signature, and check that `proof` places `msg_str` in the signed batch.
+    pub fn verify_inclusion(
+        &self,
+        msg_str: impl AsRef<[u8]>,
+        proof: &InclusionProof,
+    ) -> Result<SignBatchCommit> {
+        let commit = self.verify()?;
+        if proof.len != commit.len {
+            bail!(
+                "proof is for a batch of {} messages, not {}",
+                proof.len,
+                commit.len
+            );
+        }
+        if proof.root(&hash_message(msg_str)) != Some(commit.root) {
+            bail!("message is not in the signed batch");
+        }
+        Ok(commit)
+    }
+}
+
+fn hash_message(msg_str: impl AsRef<[u8]>) -> Message {
+    let mut msg = [0u8; 32];
+    msg.copy_from_slice(&Sha256::digest(msg_str));
+    Message { msg }
+}
+
+/// Hash a message read from `reader` a block at a time, so that messages larger than memory can
+/// be signed with [sign_digest]. Gives the same digest as hashing the whole message at once.
+pub fn hash_reader(mut reader: impl Read) -> io::Result<Message> {
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => hasher.update(&buf[..n]),
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    let mut msg = [0u8; 32];
+    msg.copy_from_slice(&hasher.finalize());
+    Ok(Message { msg })
+}
+
+/// Sign a batch of messages in one receipt. Like [sign], the host hashes each message; the guest
+/// commits the Merkle root over the digests, and [inclusion_proof] ties each message to it.
+pub fn sign_batch<M: AsRef<[u8]>>(
+    pass_str: impl AsRef<[u8]>,
+    derivation: &KeyDerivation,
+    context: &SigningContext,
+    msg_strs: &[M],
+) -> Result<BatchSignatureWithReceipt> {
+    if msg_strs.is_empty() {
+        bail!("cannot sign an empty batch");
+    }
+    let params = BatchSigningRequest {
+        passphrase: hash_passphrase(pass_str),
+        derivation: derivation.clone(),
+        context: context.clone(),
+        msgs: msg_strs.iter().map(hash_message).collect(),
+    };
+
+    let mut prover = Prover::new(SIGN_BATCH_ELF, SIGN_BATCH_ID)?;
+    let vec = to_vec(&params).unwrap();
+    prover.add_input_u32_slice(vec.as_slice());
+    let receipt = prover.run()?;
+    Ok(BatchSignatureWithReceipt { receipt })
+}
+
+/// Proof that the message at `index` is in the batch signed over `msg_strs`.
+pub fn inclusion_proof<M: AsRef<[u8]>>(msg_strs: &[M], index: usize) -> InclusionProof {
+    let msgs: Vec<Message> = msg_strs.iter().map(hash_message).collect();
+    InclusionProof::new(&msgs, index)
+}
+
+pub struct ThresholdSignatureWithReceipt {
+    receipt: Receipt,
+}
+
+impl ThresholdSignatureWithReceipt {
+    pub fn get_commit(&self) -> Result<SignThresholdCommit> {
+        let msg = &self.receipt.journal;
+        Ok(from_slice(msg.as_slice()).unwrap())
+    }
+
+    pub fn verify(&self) -> Result<SignThresholdCommit> {
+        self.receipt.verify(SIGN_THRESHOLD_ID)?;
+        self.get_commit()
+    }
+
+    /// Like [SignatureWithReceipt::verify_fresh], for a threshold signature.
+    pub fn verify_fresh(
+        &self,
+        domain: &str,
+        max_age: u64,
+        nonces: &mut NonceStore,
+    ) -> Result<SignThresholdCommit> {
+        let commit = self.verify()?;
+        check_fresh(&commit.context, domain, max_age, nonces)?;
+        Ok(commit)
+    }
+
+    /// Verify the signature, and check that it signs `msg_str` with the approval of at least
+    /// `threshold` members of `signer_set`.
+    pub fn verify_message(
+        &self,
+        msg_str: impl AsRef<[u8]>,
+        signer_set: &SignerSet,
+        threshold: u32,
+    ) -> Result<SignThresholdCommit> {
+        let commit = self.verify()?;
+        if commit.msg != hash_message(msg_str) {
+            bail!("signature is for a different message");
+        }
+        if commit.signer_set != signer_set.digest() {
+            bail!("signature is for a different signer set");
+        }
+        if commit.threshold < threshold {
+            bail!(
+                "signature requires {} approvals, not {}",
+                commit.threshold,
+                threshold
+            );
+        }
+        Ok(commit)
+    }
+}
+
+/// Sign a message on behalf of at least `threshold` members of `signer_set`. Each signer is a
+/// passphrase with the derivation of its identity; every signer must be a distinct member of the
+/// set, or no receipt is produced. The receipt does not reveal which members signed.
+pub fn sign_threshold<P: AsRef<[u8]>>(
+    signers: &[(P, KeyDerivation)],
+    signer_set: &SignerSet,
+    threshold: u32,
+    context: &SigningContext,
+    msg_str: impl AsRef<[u8]>,
+) -> Result<ThresholdSignatureWithReceipt> {
+    let params = ThresholdSigningRequest {
+        signers: signers
+            .iter()
+            .map(|(pass_str, derivation)| Signer {
+                passphrase: hash_passphrase(pass_str),
+                derivation: derivation.clone(),
+            })
+            .collect(),
+        signer_set: signer_set.clone(),
+        threshold: threshold,
+        context: context.clone(),
+        msg: hash_message(msg_str),
+    };
+
+    let mut prover = Prover::new(SIGN_THRESHOLD_ELF, SIGN_THRESHOLD_ID)?;
+    let vec = to_vec(&params).unwrap();
+    prover.add_input_u32_slice(vec.as_slice());
+    let receipt = prover.run()?;
+    Ok(ThresholdSignatureWithReceipt { receipt })
+}
+
+/// Receipt linking an old identity to the new identity that replaces it.
+pub struct RotationWithReceipt {
+    receipt: Receipt,
+}
+
+impl RotationWithReceipt {
+    pub fn get_commit(&self) -> Result<RotateCommit> {
+        let msg = &self.receipt.journal;
+        Ok(from_slice(msg.as_slice()).unwrap())
+    }
+
+    pub fn verify(&self) -> Result<RotateCommit> {
+        self.receipt.verify(ROTATE_ID)?;
+        self.get_commit()
+    }
+
+    /// Like [SignatureWithReceipt::verify_fresh], for a rotation.
+    pub fn verify_fresh(
+        &self,
+        domain: &str,
+        max_age: u64,
+        nonces: &mut NonceStore,
+    ) -> Result<RotateCommit> {
+        let commit = self.verify()?;
+        check_fresh(&commit.context, domain, max_age, nonces)?;
+        Ok(commit)
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        bincode::serialize(&self.receipt).context("failed to encode rotation")
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let receipt = bincode::deserialize(bytes).context("failed to decode rotation")?;
+        Ok(RotationWithReceipt { receipt })
+    }
+}
+
+/// Prove knowledge of the passphrases for both an old and a new identity, linking the two. Adding
+/// the result to a [RevocationList] stops signatures from the old identity being accepted.
+pub fn rotate(
+    old_pass_str: impl AsRef<[u8]>,
+    old_derivation: &KeyDerivation,
+    new_pass_str: impl AsRef<[u8]>,
+    new_derivation: &KeyDerivation,
+    context: &SigningContext,
+) -> Result<RotationWithReceipt> {
+    let params = RotationRequest {
+        old_passphrase: hash_passphrase(old_pass_str),
+        old_derivation: old_derivation.clone(),
+        new_passphrase: hash_passphrase(new_pass_str),
+        new_derivation: new_derivation.clone(),
+        context: context.clone(),
+    };
+
+    let mut prover = Prover::new(ROTATE_ELF, ROTATE_ID)?;
+    let vec = to_vec(&params).unwrap();
+    prover.add_input_u32_slice(vec.as_slice());
+    let receipt = prover.run()?;
+    Ok(RotationWithReceipt { receipt })
 }
 
 #[cfg(test)]
@@ -81,11 +649,19 @@ mod tests {
         env_logger::builder().filter_level(LevelFilter::Info).init();
     }
 
+    fn derivation() -> KeyDerivation {
+        stretched("test salt", 16)
+    }
+
+    fn context() -> SigningContext {
+        fresh_context("test")
+    }
+
     #[test]
     fn protocol() {
         let pass_str = "passphr4ase";
         let msg_str = "This message was signed by me";
-        let signing_receipt = sign(pass_str, msg_str);
+        let signing_receipt = sign(pass_str, &derivation(), &context(), msg_str);
         let signing_receipt = match signing_receipt {
             Ok(signing_receipt) => signing_receipt,
             Err(err) => panic!("Problem generating receipt: {:?}", err),
@@ -110,4 +686,369 @@ mod tests {
         log::info!("msg: {:?}", &msg_str);
         log::info!("commit: {:?}", &message);
     }
+
+    #[test]
+    fn plaintext_is_hashed_in_guest() {
+        let pass_str = "passphr4ase";
+        let msg_str = "This whole message was signed by me";
+        let context = context();
+        let signing_receipt = sign_plaintext(pass_str, &derivation(), &context, msg_str).unwrap();
+        assert_eq!(signing_receipt.get_mode(), HashMode::Guest);
+        let commit = signing_receipt.verify().unwrap();
+        assert_eq!(commit.msg.msg, Sha256::digest(msg_str).as_slice());
+
+        // Both modes commit the same digest and identity, but come from different methods.
+        let host_receipt = sign(pass_str, &derivation(), &context, msg_str).unwrap();
+        assert_eq!(host_receipt.get_commit().unwrap(), commit);
+        assert!(host_receipt
+            .receipt
+            .verify(HashMode::Guest.method_id())
+            .is_err());
+    }
+
+    #[test]
+    fn signature_file_round_trip() {
+        let pass_str = "passphr4ase";
+        let msg_str = "This message was signed by me";
+        let signing_receipt = sign_plaintext(pass_str, &derivation(), &context(), msg_str).unwrap();
+        let identity = signing_receipt.get_identity().unwrap();
+        assert_eq!(
+            parse_identity(&format_identity(&identity)).unwrap(),
+            identity
+        );
+
+        let bytes = signing_receipt.to_bytes().unwrap();
+        let signature = SignatureWithReceipt::from_bytes(&bytes).unwrap();
+        assert_eq!(signature.get_mode(), HashMode::Guest);
+        signature.verify_message(msg_str, &identity).unwrap();
+
+        // Wrong message or wrong signer.
+        assert!(signature
+            .verify_message("Some other message", &identity)
+            .is_err());
+        let other = sign("other passphrase", &derivation(), &context(), msg_str)
+            .unwrap()
+            .get_identity()
+            .unwrap();
+        assert!(signature.verify_message(msg_str, &other).is_err());
+
+        // Files from another format version or another build of the guest are turned away.
+        let mut file = SignatureFile::from_bytes(&bytes).unwrap();
+        file.version += 1;
+        assert!(file.open().is_err());
+        let mut file = SignatureFile::from_bytes(&bytes).unwrap();
+        file.method_id = vec![0; 32];
+        assert!(file.open().is_err());
+    }
+
+    #[test]
+    fn keyring_names_signer() {
+        let msg_str = "This message was signed by me";
+        let signing_receipt = sign("passphr4ase", &derivation(), &context(), msg_str).unwrap();
+        let other = sign("other passphrase", &derivation(), &context(), msg_str).unwrap();
+
+        let mut keyring = Keyring::new();
+        keyring
+            .add("alice", signing_receipt.get_identity().unwrap())
+            .unwrap();
+        let (name, _) = signing_receipt.verify_known(msg_str, &keyring).unwrap();
+        assert_eq!(name, "alice");
+        assert!(signing_receipt
+            .verify_known("Some other message", &keyring)
+            .is_err());
+        assert!(other.verify_known(msg_str, &keyring).is_err());
+    }
+
+    #[test]
+    fn stretch_matches_reference() {
+        let pass = hash_passphrase("passphr4ase");
+        let salt = [7u8; 32];
+
+        // PBKDF2-style chain over SHA-256, computed independently of the guest's hasher.
+        let mut u = Sha256::new()
+            .chain_update(pass.pass)
+            .chain_update(salt)
+            .chain_update(1u32.to_be_bytes())
+            .finalize();
+        let mut key = u;
+        for _ in 1..5 {
+            u = Sha256::new()
+                .chain_update(pass.pass)
+                .chain_update(u)
+                .finalize();
+            for (k, b) in key.iter_mut().zip(u) {
+                *k ^= b;
+            }
+        }
+        let identity = digital_signature_core::stretch(&pass, &salt, 5);
+        assert_eq!(identity.as_bytes(), Sha256::digest(key).as_slice());
+        assert_ne!(digital_signature_core::stretch(&pass, &salt, 4), identity);
+        assert_ne!(
+            digital_signature_core::stretch(&pass, &[8u8; 32], 5),
+            identity
+        );
+    }
+
+    #[test]
+    fn stretched_identities() {
+        let pass_str = "passphr4ase";
+        let msg_str = "This message was signed by me";
+        let signing_receipt = sign(pass_str, &derivation(), &context(), msg_str).unwrap();
+        let commit = signing_receipt.verify().unwrap();
+        assert_eq!(commit.derivation, derivation());
+        let KeyDerivation::Stretched { salt, iterations } = derivation() else {
+            unreachable!()
+        };
+        assert_eq!(
+            commit.identity,
+            digital_signature_core::stretch(&hash_passphrase(pass_str), &salt, iterations)
+        );
+
+        // The same passphrase gives unrelated identities under other salts or the legacy scheme.
+        let other_salt = sign(pass_str, &stretched("other salt", 16), &context(), msg_str).unwrap();
+        assert_ne!(other_salt.get_identity().unwrap(), commit.identity);
+        let legacy = sign(pass_str, &KeyDerivation::Legacy, &context(), msg_str).unwrap();
+        let legacy = legacy.verify().unwrap();
+        assert_eq!(legacy.derivation, KeyDerivation::Legacy);
+        assert_ne!(legacy.identity, commit.identity);
+    }
+
+    #[test]
+    fn batch_inclusion() {
+        let pass_str = "passphr4ase";
+        let msg_strs = [
+            "artifact-1.tar.gz",
+            "artifact-2.tar.gz",
+            "artifact-3.tar.gz",
+        ];
+        let batch = sign_batch(pass_str, &derivation(), &context(), &msg_strs).unwrap();
+        let commit = batch.verify().unwrap();
+        assert_eq!(commit.len, 3);
+        assert_eq!(
+            commit.identity,
+            sign(pass_str, &derivation(), &context(), msg_strs[0])
+                .unwrap()
+                .get_identity()
+                .unwrap()
+        );
+
+        for (index, msg_str) in msg_strs.iter().enumerate() {
+            let proof = inclusion_proof(&msg_strs, index);
+            batch.verify_inclusion(msg_str, &proof).unwrap();
+        }
+
+        // A message outside the batch, or a proof from a different batch, does not verify.
+        let proof = inclusion_proof(&msg_strs, 1);
+        assert!(batch.verify_inclusion("artifact-4.tar.gz", &proof).is_err());
+        let other = ["artifact-2.tar.gz", "artifact-4.tar.gz"];
+        let proof = inclusion_proof(&other, 0);
+        assert!(batch.verify_inclusion(other[0], &proof).is_err());
+        assert!(sign_batch::<&str>(pass_str, &derivation(), &context(), &[]).is_err());
+
+        let mut nonces = NonceStore::new();
+        assert!(batch.verify_fresh("other", 60, &mut nonces).is_err());
+        batch.verify_fresh("test", 60, &mut nonces).unwrap();
+        assert!(batch.verify_fresh("test", 60, &mut nonces).is_err());
+    }
+
+    #[test]
+    fn rotated_identity() {
+        let msg_str = "This message was signed by me";
+        let old_sig = sign("passphr4ase", &derivation(), &context(), msg_str).unwrap();
+        let new_derivation = stretched("new salt", 16);
+        let new_sig = sign("n3w passphrase", &new_derivation, &context(), msg_str).unwrap();
+
+        let rotation = rotate(
+            "passphr4ase",
+            &derivation(),
+            "n3w passphrase",
+            &new_derivation,
+            &context(),
+        )
+        .unwrap();
+        let rotation = RotationWithReceipt::from_bytes(&rotation.to_bytes().unwrap()).unwrap();
+        let commit = rotation.verify().unwrap();
+        assert_eq!(commit.old_identity, old_sig.get_identity().unwrap());
+        assert_eq!(commit.new_identity, new_sig.get_identity().unwrap());
+        let mut nonces = NonceStore::new();
+        rotation.verify_fresh("test", 60, &mut nonces).unwrap();
+        assert!(rotation.verify_fresh("test", 60, &mut nonces).is_err());
+
+        let mut revocations = RevocationList::new();
+        old_sig.verify_with(&revocations).unwrap();
+        revocations.add_rotation(&rotation).unwrap();
+        let err = old_sig.verify_with(&revocations).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<RevokedIdentity>().unwrap().revocation,
+            Revocation::Rotated {
+                new_identity: commit.new_identity
+            }
+        );
+        new_sig.verify_with(&revocations).unwrap();
+
+        revocations.revoke(commit.new_identity).unwrap();
+        assert!(new_sig.verify_with(&revocations).is_err());
+
+        // A rotation has to change the identity.
+        assert!(rotate(
+            "passphr4ase",
+            &derivation(),
+            "passphr4ase",
+            &derivation(),
+            &context()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn threshold_signature() {
+        let msg_str = "Release v1.0.0";
+        let passes = [
+            "alice's passphrase",
+            "bob's passphrase",
+            "carol's passphrase",
+        ];
+        let identity = |pass_str| {
+            sign(pass_str, &derivation(), &context(), msg_str)
+                .unwrap()
+                .get_identity()
+                .unwrap()
+        };
+        let signer_set = SignerSet::new(passes.iter().map(|pass| identity(*pass)).collect());
+        let signers = |passes: &[&str]| -> Vec<(String, KeyDerivation)> {
+            passes
+                .iter()
+                .map(|pass| (pass.to_string(), derivation()))
+                .collect()
+        };
+
+        let signature =
+            sign_threshold(&signers(&passes[..2]), &signer_set, 2, &context(), msg_str).unwrap();
+        let commit = signature.verify_message(msg_str, &signer_set, 2).unwrap();
+        assert_eq!(commit.threshold, 2);
+        assert_eq!(commit.signer_set, signer_set.digest());
+        let mut nonces = NonceStore::new();
+        signature.verify_fresh("test", 60, &mut nonces).unwrap();
+        assert!(signature.verify_fresh("test", 60, &mut nonces).is_err());
+        assert!(signature.verify_message(msg_str, &signer_set, 3).is_err());
+        assert!(signature
+            .verify_message("Release v1.0.1", &signer_set, 2)
+            .is_err());
+        let other_set = SignerSet::new(signer_set.identities()[..2].to_vec());
+        assert!(signature.verify_message(msg_str, &other_set, 2).is_err());
+
+        // Too few signers, a repeated signer, or an outsider can't make a signature.
+        assert!(
+            sign_threshold(&signers(&passes[..1]), &signer_set, 2, &context(), msg_str).is_err()
+        );
+        let repeated = signers(&[passes[0], passes[0]]);
+        assert!(sign_threshold(&repeated, &signer_set, 2, &context(), msg_str).is_err());
+        let outsider = signers(&[passes[0], "mallory's passphrase"]);
+        assert!(sign_threshold(&outsider, &signer_set, 2, &context(), msg_str).is_err());
+        assert!(sign_threshold(&signers(&passes), &signer_set, 4, &context(), msg_str).is_err());
+    }
+
+    #[test]
+    fn replayed_signature() {
+        let msg_str = "This message was signed by me";
+        let signing_receipt = sign("passphr4ase", &derivation(), &context(), msg_str).unwrap();
+        let commit = signing_receipt.verify().unwrap();
+        assert_eq!(commit.context.domain, "test");
+
+        let mut nonces = NonceStore::new();
+        assert!(signing_receipt
+            .verify_fresh("other", 60, &mut nonces)
+            .is_err());
+        signing_receipt
+            .verify_fresh("test", 60, &mut nonces)
+            .unwrap();
+        assert!(nonces.contains(&commit.context.nonce));
+        assert!(signing_receipt
+            .verify_fresh("test", 60, &mut nonces)
+            .is_err());
+
+        // Every signature gets its own nonce.
+        let again = sign("passphr4ase", &derivation(), &context(), msg_str).unwrap();
+        again.verify_fresh("test", 60, &mut nonces).unwrap();
+    }
+
+    #[test]
+    fn verify_all_checks_everything() {
+        let msg_str = "This message was signed by me";
+        let msg = hash_message(msg_str);
+        let signing_receipt = sign("passphr4ase", &derivation(), &context(), msg_str).unwrap();
+        let identity = signing_receipt.get_identity().unwrap();
+        let mut keyring = Keyring::new();
+        keyring.add("alice", identity).unwrap();
+        let revocations = RevocationList::new();
+        let mut revoked = RevocationList::new();
+        revoked.revoke(identity).unwrap();
+        let policy = |signer, revocations| VerifyPolicy {
+            signer,
+            revocations,
+            domain: "test",
+            max_age: 60,
+        };
+
+        // A failed check leaves the nonce unused.
+        let mut nonces = NonceStore::new();
+        let known = policy(ExpectedSigner::Known(&keyring), &revocations);
+        let wrong_msg = hash_message("Some other message");
+        assert!(signing_receipt
+            .verify_all(&wrong_msg, &known, &mut nonces)
+            .is_err());
+        let other_domain = VerifyPolicy {
+            domain: "other",
+            ..known
+        };
+        assert!(signing_receipt
+            .verify_all(&msg, &other_domain, &mut nonces)
+            .is_err());
+        let stranger = Keyring::new();
+        let unknown = policy(ExpectedSigner::Known(&stranger), &revocations);
+        assert!(signing_receipt
+            .verify_all(&msg, &unknown, &mut nonces)
+            .is_err());
+        assert!(nonces.is_empty());
+
+        signing_receipt
+            .verify_all(&msg, &known, &mut nonces)
+            .unwrap();
+        assert!(signing_receipt
+            .verify_all(&msg, &known, &mut nonces)
+            .is_err());
+
+        let again = sign("passphr4ase", &derivation(), &context(), msg_str).unwrap();
+        let revoked = policy(ExpectedSigner::Identity(&identity), &revoked);
+        assert!(again.verify_all(&msg, &revoked, &mut nonces).is_err());
+        assert_eq!(nonces.len(), 1);
+    }
+
+    #[test]
+    fn context_age() {
+        let context = SigningContext {
+            domain: "test".to_string(),
+            nonce: [0; 32],
+            timestamp: 1_000,
+        };
+        check_context(&context, "test", 60, 1_000).unwrap();
+        check_context(&context, "test", 60, 1_060).unwrap();
+        assert!(check_context(&context, "test", 60, 1_061).is_err());
+        assert!(check_context(&context, "other", 60, 1_000).is_err());
+
+        // Small clock differences are tolerated, but not signatures from the far future.
+        check_context(&context, "test", 60, 1_000 - MAX_CLOCK_SKEW).unwrap();
+        assert!(check_context(&context, "test", 60, 999 - MAX_CLOCK_SKEW).is_err());
+    }
+
+    #[test]
+    fn streamed_digest() {
+        let msg_str = "This message was signed by me, one block at a time".repeat(5000);
+        let msg = hash_reader(msg_str.as_bytes()).unwrap();
+        assert_eq!(msg, hash_message(&msg_str));
+
+        let signing_receipt = sign_digest("passphr4ase", &derivation(), &context(), msg).unwrap();
+        let identity = signing_receipt.get_identity().unwrap();
+        signing_receipt.verify_message(&msg_str, &identity).unwrap();
+    }
 }
@@ -12,10 +12,67 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use clap::{Arg, Command};
-use digital_signature::sign;
+use std::fs::{self, File};
+use std::io::{self, Read};
+
+use anyhow::{bail, Context};
+use clap::{Arg, ArgMatches, Command};
+use digital_signature::{
+    format_identity, fresh_context, hash_reader, parse_identity, rotate, sign_digest,
+    sign_plaintext, stretched, ExpectedSigner, HexCommit, KeyDerivation, Keyring, NonceStore,
+    Revocation, RevocationList, RotationWithReceipt, SignatureWithReceipt, VerifyPolicy,
+    DEFAULT_ITERATIONS,
+};
 use log::LevelFilter;
-use sha2::{Digest, Sha256};
+use serde_json::json;
+
+const DEFAULT_KEYRING: &str = "keyring.txt";
+const DEFAULT_REVOCATIONS: &str = "revocations.txt";
+const DEFAULT_NONCES: &str = "nonces.txt";
+const DEFAULT_DOMAIN: &str = "digital-signature";
+const DEFAULT_MAX_AGE: &str = "86400";
+
+fn domain_arg() -> Arg<'static> {
+    Arg::new("domain")
+        .long("domain")
+        .takes_value(true)
+        .default_value(DEFAULT_DOMAIN)
+        .help("Tag naming the application the signature is for")
+}
+
+fn keyring_arg() -> Arg<'static> {
+    Arg::new("keyring")
+        .long("keyring")
+        .takes_value(true)
+        .default_value(DEFAULT_KEYRING)
+        .help("File mapping names to known identities")
+}
+
+fn revocations_arg() -> Arg<'static> {
+    Arg::new("revocations")
+        .long("revocations")
+        .takes_value(true)
+        .default_value(DEFAULT_REVOCATIONS)
+        .help("File listing rotated and revoked identities")
+}
+
+fn message_arg() -> Arg<'static> {
+    Arg::new("message").help("The message; if neither it nor --file is given, stdin is read")
+}
+
+fn json_arg() -> Arg<'static> {
+    Arg::new("json")
+        .long("json")
+        .help("Print the result to stdout as JSON, including any error")
+}
+
+fn file_arg() -> Arg<'static> {
+    Arg::new("file")
+        .long("file")
+        .takes_value(true)
+        .conflicts_with("message")
+        .help("Read the message from a file")
+}
 
 fn main() {
     env_logger::builder().filter_level(LevelFilter::Info).init();
@@ -24,36 +81,556 @@ fn main() {
         .version("0.1.0")
         .author("Risc0, Inc.")
         .about("Digital signatures with Risc0")
-        .arg(Arg::new("message").required(true))
-        .arg(
-            Arg::new("passphrase")
-                .long("passphrase")
-                .required(true)
-                .takes_value(true),
+        .subcommand_required(true)
+        .subcommand(
+            Command::new("sign")
+                .about("Sign a message and write the signature to a file")
+                .arg(message_arg())
+                .arg(file_arg())
+                .arg(json_arg())
+                .arg(
+                    Arg::new("passphrase")
+                        .long("passphrase")
+                        .takes_value(true)
+                        .help("Prompted for if neither it nor --passphrase-file is given"),
+                )
+                .arg(
+                    Arg::new("passphrase-file")
+                        .long("passphrase-file")
+                        .takes_value(true)
+                        .conflicts_with("passphrase")
+                        .help("Read the passphrase from the first line of a file"),
+                )
+                .arg(
+                    Arg::new("plaintext")
+                        .long("plaintext")
+                        .help("Pass the whole message to the zkVM and hash it there"),
+                )
+                .arg(
+                    Arg::new("salt")
+                        .long("salt")
+                        .takes_value(true)
+                        .required_unless_present("legacy")
+                        .help("Salt for the signer's identity; reuse it to keep the same identity"),
+                )
+                .arg(
+                    Arg::new("iterations")
+                        .long("iterations")
+                        .takes_value(true)
+                        .conflicts_with("legacy")
+                        .help("Number of key derivation iterations for the signer's identity"),
+                )
+                .arg(
+                    Arg::new("legacy")
+                        .long("legacy")
+                        .conflicts_with("salt")
+                        .help("Derive the identity with the old unsalted scheme"),
+                )
+                .arg(domain_arg())
+                .arg(
+                    Arg::new("out")
+                        .short('o')
+                        .long("out")
+                        .required(true)
+                        .takes_value(true)
+                        .help("Where to write the signature file"),
+                ),
+        )
+        .subcommand(
+            Command::new("verify")
+                .about("Check that a signature file signs a message on behalf of an identity")
+                .arg(message_arg())
+                .arg(file_arg())
+                .arg(json_arg())
+                .arg(
+                    Arg::new("signature")
+                        .long("signature")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::new("identity")
+                        .long("identity")
+                        .takes_value(true)
+                        .help(
+                            "Expected signer, as a hex digest or a name in the keyring. If \
+                             omitted, any identity in the keyring is accepted",
+                        ),
+                )
+                .arg(keyring_arg())
+                .arg(revocations_arg())
+                .arg(domain_arg())
+                .arg(
+                    Arg::new("max-age")
+                        .long("max-age")
+                        .takes_value(true)
+                        .default_value(DEFAULT_MAX_AGE)
+                        .help(
+                            "Reject signatures older than this many seconds. May not exceed the \
+                             time the nonce store keeps nonces for",
+                        ),
+                )
+                .arg(
+                    Arg::new("nonces")
+                        .long("nonces")
+                        .takes_value(true)
+                        .default_value(DEFAULT_NONCES)
+                        .help("File recording the nonces of signatures already accepted"),
+                ),
+        )
+        .subcommand(
+            Command::new("rotate")
+                .about("Link an identity to a new one, and write the proof to a file")
+                .arg(Arg::new("passphrase").long("passphrase").takes_value(true))
+                .arg(
+                    Arg::new("passphrase-file")
+                        .long("passphrase-file")
+                        .takes_value(true)
+                        .conflicts_with("passphrase"),
+                )
+                .arg(
+                    Arg::new("salt")
+                        .long("salt")
+                        .takes_value(true)
+                        .required_unless_present("legacy"),
+                )
+                .arg(
+                    Arg::new("iterations")
+                        .long("iterations")
+                        .takes_value(true)
+                        .conflicts_with("legacy"),
+                )
+                .arg(
+                    Arg::new("legacy")
+                        .long("legacy")
+                        .conflicts_with("salt")
+                        .help("The old identity was derived with the unsalted scheme"),
+                )
+                .arg(
+                    Arg::new("new-passphrase")
+                        .long("new-passphrase")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::new("new-passphrase-file")
+                        .long("new-passphrase-file")
+                        .takes_value(true)
+                        .conflicts_with("new-passphrase"),
+                )
+                .arg(
+                    Arg::new("new-salt")
+                        .long("new-salt")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::new("new-iterations")
+                        .long("new-iterations")
+                        .takes_value(true),
+                )
+                .arg(domain_arg())
+                .arg(
+                    Arg::new("out")
+                        .short('o')
+                        .long("out")
+                        .required(true)
+                        .takes_value(true)
+                        .help("Where to write the rotation proof"),
+                ),
+        )
+        .subcommand(
+            Command::new("revocations")
+                .about("Manage the identities whose signatures are no longer accepted")
+                .subcommand_required(true)
+                .arg(revocations_arg())
+                .subcommand(
+                    Command::new("add-rotation")
+                        .about("Verify a rotation proof and stop trusting its old identity")
+                        .arg(Arg::new("rotation").required(true)),
+                )
+                .subcommand(
+                    Command::new("revoke")
+                        .about("Stop trusting an identity")
+                        .arg(Arg::new("identity").required(true)),
+                )
+                .subcommand(Command::new("list")),
+        )
+        .subcommand(
+            Command::new("keyring")
+                .about("Manage the names of known identities")
+                .subcommand_required(true)
+                .arg(keyring_arg())
+                .subcommand(
+                    Command::new("add")
+                        .arg(Arg::new("name").required(true))
+                        .arg(Arg::new("identity").required(true)),
+                )
+                .subcommand(Command::new("remove").arg(Arg::new("name").required(true)))
+                .subcommand(Command::new("list")),
         )
         .get_matches();
 
-    let message = matches.value_of("message").unwrap();
-    let passphrase = matches.value_of("passphrase").unwrap();
-    let signing_receipt = sign(&passphrase, &message).unwrap();
+    match matches.subcommand() {
+        Some(("sign", matches)) => run_sign(matches),
+        Some(("verify", matches)) => run_verify(matches),
+        Some(("rotate", matches)) => run_rotate(matches),
+        Some(("revocations", matches)) => run_revocations(matches),
+        Some(("keyring", matches)) => run_keyring(matches),
+        _ => unreachable!(),
+    }
+}
+
+/// Where the message comes from: the positional argument, `--file`, or stdin.
+fn message_reader(matches: &ArgMatches) -> anyhow::Result<Box<dyn Read>> {
+    if let Some(message) = matches.value_of("message") {
+        return Ok(Box::new(io::Cursor::new(message.as_bytes().to_vec())));
+    }
+    match matches.value_of("file") {
+        Some(path) => Ok(Box::new(File::open(path)?)),
+        None => Ok(Box::new(io::stdin())),
+    }
+}
+
+fn message_source(matches: &ArgMatches) -> String {
+    match (matches.value_of("message"), matches.value_of("file")) {
+        (Some(message), _) => format!("{:?}", message),
+        (None, Some(path)) => format!("file {}", path),
+        (None, None) => "stdin".to_string(),
+    }
+}
+
+/// Passphrase given by the `name` argument, the first line of the `file` argument, or else
+/// prompted for on the terminal.
+fn read_passphrase(
+    matches: &ArgMatches,
+    name: &str,
+    file: &str,
+    prompt: &str,
+) -> anyhow::Result<String> {
+    if let Some(passphrase) = matches.value_of(name) {
+        return Ok(passphrase.to_string());
+    }
+    if let Some(path) = matches.value_of(file) {
+        let contents = fs::read_to_string(path)?;
+        let passphrase = contents.lines().next().unwrap_or_default();
+        if passphrase.is_empty() {
+            bail!("{} is empty", path);
+        }
+        return Ok(passphrase.to_string());
+    }
+    Ok(rpassword::prompt_password(prompt)?)
+}
+
+/// Like [read_passphrase] for the new passphrase of a rotation, which has to be typed twice when
+/// prompted for, so that a typo doesn't leave the signer locked out of their new identity.
+fn read_new_passphrase(matches: &ArgMatches) -> String {
+    let prompt = "New passphrase: ";
+    let passphrase = exit_on_err(read_passphrase(
+        matches,
+        "new-passphrase",
+        "new-passphrase-file",
+        prompt,
+    ));
+    if matches.is_present("new-passphrase") || matches.is_present("new-passphrase-file") {
+        return passphrase;
+    }
+    let repeated = exit_on_err(
+        rpassword::prompt_password("Repeat new passphrase: ").map_err(anyhow::Error::from),
+    );
+    if repeated != passphrase {
+        log::error!("New passphrases do not match");
+        std::process::exit(2);
+    }
+    passphrase
+}
+
+fn run_sign(matches: &ArgMatches) {
+    let errors = JsonErrors::new(matches, json!({}));
+    let passphrase = errors.check(read_passphrase(
+        matches,
+        "passphrase",
+        "passphrase-file",
+        "Passphrase: ",
+    ));
+    let out = matches.value_of("out").unwrap();
+    let derivation = errors.check(derivation(
+        matches,
+        "salt",
+        "iterations",
+        matches.is_present("legacy"),
+    ));
+    let context = fresh_context(matches.value_of("domain").unwrap());
+    let mut reader = errors.check(message_reader(matches));
+    let signing_receipt = if matches.is_present("plaintext") {
+        // The guest hashes the message itself, so all of it has to be passed in at once.
+        let mut message = Vec::new();
+        errors.check(
+            reader
+                .read_to_end(&mut message)
+                .map_err(anyhow::Error::from),
+        );
+        errors.check(sign_plaintext(passphrase, &derivation, &context, message))
+    } else {
+        let message = errors.check(hash_reader(reader).map_err(anyhow::Error::from));
+        errors.check(sign_digest(passphrase, &derivation, &context, message))
+    };
 
     log::info!("Inputs");
-    log::info!("\tmessage: {:?}", &message);
+    log::info!("\tmessage: {}", message_source(matches));
     log::info!("Commitment:");
     log::info!("\tmessage: {:?}", &signing_receipt.get_message().unwrap());
-    log::info!("\tidentity: {:?}", &signing_receipt.get_identity().unwrap());
+    let identity = signing_receipt.get_identity().unwrap();
+    log::info!("\tidentity: {}", format_identity(&identity));
+    log::info!(
+        "\tderivation: {:?}",
+        signing_receipt.get_commit().unwrap().derivation
+    );
+    log::info!("\tdomain: {}", context.domain);
+    log::info!("\ttimestamp: {}", context.timestamp);
+    let bytes = errors.check(signing_receipt.to_bytes());
+    errors.check(fs::write(out, bytes).map_err(anyhow::Error::from));
+    log::info!("Signature written to {}", out);
+    if matches.is_present("json") {
+        let commit = HexCommit::from(signing_receipt.get_commit().unwrap());
+        println!("{}", json!({ "signature": out, "commit": commit }));
+    }
+}
+
+/// Key derivation given by the `salt` and `iterations` arguments, or the legacy scheme.
+fn derivation(
+    matches: &ArgMatches,
+    salt: &str,
+    iterations: &str,
+    legacy: bool,
+) -> anyhow::Result<KeyDerivation> {
+    if legacy {
+        return Ok(KeyDerivation::Legacy);
+    }
+    let count = match matches.value_of(iterations) {
+        Some(count) => count
+            .parse()
+            .with_context(|| format!("--{} is not a number", iterations))?,
+        None => DEFAULT_ITERATIONS,
+    };
+    if count == 0 {
+        bail!("--{} must be at least 1", iterations);
+    }
+    Ok(stretched(matches.value_of(salt).unwrap(), count))
+}
+
+fn run_rotate(matches: &ArgMatches) {
+    let out = matches.value_of("out").unwrap();
+    let rotation = exit_on_err(rotate(
+        exit_on_err(read_passphrase(
+            matches,
+            "passphrase",
+            "passphrase-file",
+            "Old passphrase: ",
+        )),
+        &exit_on_err(derivation(
+            matches,
+            "salt",
+            "iterations",
+            matches.is_present("legacy"),
+        )),
+        read_new_passphrase(matches),
+        &exit_on_err(derivation(matches, "new-salt", "new-iterations", false)),
+        &fresh_context(matches.value_of("domain").unwrap()),
+    ));
+    let commit = exit_on_err(rotation.get_commit());
+    log::info!("Commitment:");
+    log::info!("\told identity: {}", format_identity(&commit.old_identity));
+    log::info!("\tnew identity: {}", format_identity(&commit.new_identity));
+    log::info!("\tnew derivation: {:?}", commit.new_derivation);
+    let bytes = exit_on_err(rotation.to_bytes());
+    exit_on_err(fs::write(out, bytes).map_err(anyhow::Error::from));
+    log::info!("Rotation written to {}", out);
+}
+
+fn run_revocations(matches: &ArgMatches) {
+    let path = matches.value_of("revocations").unwrap();
+    let mut revocations = exit_on_err(RevocationList::load(path));
+    match matches.subcommand() {
+        Some(("add-rotation", matches)) => {
+            let rotation = exit_on_err(
+                fs::read(matches.value_of("rotation").unwrap())
+                    .map_err(anyhow::Error::from)
+                    .and_then(|bytes| RotationWithReceipt::from_bytes(&bytes)),
+            );
+            exit_on_err(revocations.add_rotation(&rotation));
+            exit_on_err(revocations.save(path));
+            let commit = rotation.get_commit().unwrap();
+            match revocations.get(&commit.old_identity) {
+                Some(Revocation::Revoked) => log::warn!(
+                    "{} was already rotated to another identity, so it and both new identities \
+                     are revoked",
+                    format_identity(&commit.old_identity)
+                ),
+                _ => log::info!(
+                    "{} rotated to {}",
+                    format_identity(&commit.old_identity),
+                    format_identity(&commit.new_identity)
+                ),
+            }
+        }
+        Some(("revoke", matches)) => {
+            let identity = exit_on_err(parse_identity(matches.value_of("identity").unwrap()));
+            exit_on_err(revocations.revoke(identity));
+            exit_on_err(revocations.save(path));
+            log::info!("Revoked {}", format_identity(&identity));
+        }
+        Some(("list", _)) => {
+            for (identity, revocation) in revocations.iter() {
+                match revocation {
+                    Revocation::Rotated { new_identity } => println!(
+                        "rotated {} {}",
+                        format_identity(&identity),
+                        format_identity(&new_identity)
+                    ),
+                    Revocation::Revoked => println!("revoked {}", format_identity(&identity)),
+                }
+            }
+        }
+        _ => unreachable!(),
+    }
+}
+
+fn exit_on_err<T>(result: anyhow::Result<T>) -> T {
+    match result {
+        Ok(value) => value,
+        Err(err) => {
+            log::error!("{:#}", err);
+            std::process::exit(2);
+        }
+    }
+}
+
+/// Error handling for commands that take `--json`: errors are logged as usual, and with `--json`
+/// also printed to stdout as the `error` field of `report`, so that tools reading stdout always
+/// get a result.
+struct JsonErrors {
+    json: bool,
+    report: serde_json::Value,
+}
+
+impl JsonErrors {
+    fn new(matches: &ArgMatches, report: serde_json::Value) -> Self {
+        JsonErrors {
+            json: matches.is_present("json"),
+            report,
+        }
+    }
+
+    /// Report `err` and exit with `code`.
+    fn exit(&self, err: anyhow::Error, code: i32) -> ! {
+        log::error!("{:#}", err);
+        if self.json {
+            let mut report = self.report.clone();
+            report["error"] = json!(format!("{:#}", err));
+            println!("{}", report);
+        }
+        std::process::exit(code);
+    }
+
+    /// Like [exit_on_err].
+    fn check<T>(&self, result: anyhow::Result<T>) -> T {
+        match result {
+            Ok(value) => value,
+            Err(err) => self.exit(err, 2),
+        }
+    }
+}
+
+fn run_verify(matches: &ArgMatches) {
+    let errors = JsonErrors::new(matches, json!({ "valid": false }));
+    let reader = errors.check(message_reader(matches));
+    let message = errors.check(hash_reader(reader).map_err(anyhow::Error::from));
+    let keyring = errors.check(Keyring::load(matches.value_of("keyring").unwrap()));
+    let revocations = errors.check(RevocationList::load(
+        matches.value_of("revocations").unwrap(),
+    ));
+    let domain = matches.value_of("domain").unwrap();
+    let max_age: u64 = errors.check(
+        matches
+            .value_of("max-age")
+            .unwrap()
+            .parse()
+            .map_err(anyhow::Error::from),
+    );
+    let nonces_path = matches.value_of("nonces").unwrap();
+    let mut nonces = errors.check(NonceStore::load(nonces_path));
+    let signature = errors.check(
+        fs::read(matches.value_of("signature").unwrap())
+            .map_err(anyhow::Error::from)
+            .and_then(|bytes| SignatureWithReceipt::from_bytes(&bytes)),
+    );
+
     log::info!("Integrity Checks:");
-    let message_hash = &signing_receipt.get_message().unwrap().msg;
-    let expected_message_hash = Sha256::digest(message);
-    if message_hash != expected_message_hash.as_slice() {
-        log::error!("Message commitment does not match given message!");
-        std::process::exit(1);
-    }
-    log::info!("\tmessage: valid");
-    if signing_receipt.verify().is_err() {
-        log::error!("Receipt is invalid!");
-        log::error!("{}", signing_receipt.verify().unwrap_err());
-        std::process::exit(1);
-    }
-    log::info!("\treceipt: valid");
+    let identity = matches
+        .value_of("identity")
+        .map(|identity| match keyring.get(identity) {
+            Some(known) => *known,
+            None => errors.check(parse_identity(identity)),
+        });
+    let policy = VerifyPolicy {
+        signer: match &identity {
+            Some(identity) => ExpectedSigner::Identity(identity),
+            None => ExpectedSigner::Known(&keyring),
+        },
+        revocations: &revocations,
+        domain,
+        max_age,
+    };
+    let commit = match signature.verify_all(&message, &policy, &mut nonces) {
+        Ok(commit) => commit,
+        Err(err) => {
+            log::error!("Signature is invalid!");
+            errors.exit(err, 1);
+        }
+    };
+    errors.check(nonces.save(nonces_path));
+    log::info!("\tsignature: valid");
+    log::info!("\tidentity: {}", format_identity(&commit.identity));
+    log::info!("\tsigned at: {}", commit.context.timestamp);
+    let signer = keyring.name_of(&commit.identity);
+    match signer {
+        Some(name) => log::info!("\tsigned by: {}", name),
+        None => log::info!("\tsigned by: unknown"),
+    }
+    if matches.is_present("json") {
+        let commit = HexCommit::from(commit);
+        println!(
+            "{}",
+            json!({ "valid": true, "signer": signer, "commit": commit })
+        );
+    }
+}
+
+fn run_keyring(matches: &ArgMatches) {
+    let path = matches.value_of("keyring").unwrap();
+    let mut keyring = exit_on_err(Keyring::load(path));
+    match matches.subcommand() {
+        Some(("add", matches)) => {
+            let name = matches.value_of("name").unwrap();
+            let identity = exit_on_err(parse_identity(matches.value_of("identity").unwrap()));
+            exit_on_err(keyring.add(name, identity));
+            exit_on_err(keyring.save(path));
+            log::info!("Added {} to {}", name, path);
+        }
+        Some(("remove", matches)) => {
+            let name = matches.value_of("name").unwrap();
+            if keyring.remove(name).is_none() {
+                log::error!("{} is not in {}", name, path);
+                std::process::exit(2);
+            }
+            exit_on_err(keyring.save(path));
+            log::info!("Removed {} from {}", name, path);
+        }
+        Some(("list", _)) => {
+            for (name, identity) in keyring.iter() {
+                println!("{} {}", name, format_identity(identity));
+            }
+        }
+        _ => unreachable!(),
+    }
 }
@@ -0,0 +1,195 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::str::FromStr;
+use std::{fmt, fs, io};
+
+use anyhow::{anyhow, bail, Context};
+use risc0_zkvm::Result;
+
+/// How long a nonce store keeps nonces unless told otherwise: 30 days, in seconds.
+pub const DEFAULT_RETENTION: u64 = 30 * 24 * 60 * 60;
+
+/// Local record of the signature nonces a verifier has already accepted, so that a signature
+/// can't be replayed.
+///
+/// Nonces are kept for a fixed retention window, which is saved with the store. Signatures older
+/// than the window can no longer be told apart from new ones, so a verifier must not accept them:
+/// [NonceStore::check_max_age] fails for a maximum age longer than the window.
+///
+/// On disk the store is a text file starting with a `retention seconds` line, followed by one
+/// `nonce timestamp` pair per line, where the nonce is hex and the timestamp is the signature's,
+/// in seconds since the UNIX epoch. A file without a `retention` line keeps the default.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct NonceStore {
+    retention: u64,
+    // Keyed by the hex encoding of the nonce.
+    seen: BTreeMap<String, u64>,
+}
+
+impl Default for NonceStore {
+    fn default() -> Self {
+        Self::with_retention(DEFAULT_RETENTION)
+    }
+}
+
+impl NonceStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// An empty store that keeps nonces for `retention` seconds.
+    pub fn with_retention(retention: u64) -> Self {
+        NonceStore {
+            retention,
+            seen: BTreeMap::new(),
+        }
+    }
+
+    pub fn retention(&self) -> u64 {
+        self.retention
+    }
+
+    /// Fail if signatures up to `max_age` seconds old could outlive their nonces in this store.
+    pub fn check_max_age(&self, max_age: u64) -> Result<()> {
+        if max_age > self.retention {
+            bail!(
+                "maximum age of {}s is longer than the {}s the nonce store keeps nonces for",
+                max_age,
+                self.retention
+            );
+        }
+        Ok(())
+    }
+
+    /// Read a nonce store from `path`. A missing file is read as an empty store.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        match fs::read_to_string(path) {
+            Ok(contents) => contents
+                .parse()
+                .with_context(|| format!("failed to read nonce store {}", path.display())),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Self::new()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        fs::write(path, self.to_string())?;
+        Ok(())
+    }
+
+    pub fn contains(&self, nonce: &[u8; 32]) -> bool {
+        self.seen.contains_key(&hex::encode(nonce))
+    }
+
+    /// Record a nonce, failing if it was seen before.
+    pub fn insert(&mut self, nonce: &[u8; 32], timestamp: u64) -> Result<()> {
+        let key = hex::encode(nonce);
+        if self.seen.contains_key(&key) {
+            bail!("nonce {} was already used", key);
+        }
+        self.seen.insert(key, timestamp);
+        Ok(())
+    }
+
+    /// Forget nonces of signatures that are older than the retention window at time `now`.
+    pub fn prune(&mut self, now: u64) {
+        let cutoff = now.saturating_sub(self.retention);
+        self.seen.retain(|_, timestamp| *timestamp >= cutoff);
+    }
+
+    pub fn len(&self) -> usize {
+        self.seen.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.seen.is_empty()
+    }
+}
+
+impl FromStr for NonceStore {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut store = NonceStore::new();
+        for (number, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(retention) = line.strip_prefix("retention ") {
+                store.retention = retention
+                    .trim()
+                    .parse()
+                    .with_context(|| format!("line {}", number + 1))?;
+                continue;
+            }
+            let parsed = line
+                .split_once(char::is_whitespace)
+                .ok_or_else(|| anyhow!("expected a nonce and a timestamp"))
+                .and_then(|(nonce, timestamp)| {
+                    let nonce: [u8; 32] = hex::decode(nonce)?
+                        .try_into()
+                        .map_err(|_| anyhow!("nonce must be 32 bytes"))?;
+                    store.insert(&nonce, timestamp.trim().parse()?)
+                });
+            parsed.with_context(|| format!("line {}", number + 1))?;
+        }
+        Ok(store)
+    }
+}
+
+impl fmt::Display for NonceStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "retention {}", self.retention)?;
+        for (nonce, timestamp) in &self.seen {
+            writeln!(f, "{} {}", nonce, timestamp)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_replays() {
+        let mut store = NonceStore::new();
+        store.insert(&[1; 32], 100).unwrap();
+        store.insert(&[2; 32], 200).unwrap();
+        assert!(store.insert(&[1; 32], 300).is_err());
+
+        let parsed: NonceStore = store.to_string().parse().unwrap();
+        assert_eq!(parsed, store);
+        assert!(parsed.contains(&[2; 32]));
+        assert!("01 100".parse::<NonceStore>().is_err());
+
+        let mut store = NonceStore::with_retention(100);
+        store.insert(&[1; 32], 100).unwrap();
+        store.insert(&[2; 32], 200).unwrap();
+        let parsed: NonceStore = store.to_string().parse().unwrap();
+        assert_eq!(parsed.retention(), 100);
+        store.prune(250);
+        assert!(!store.contains(&[1; 32]));
+        assert!(store.contains(&[2; 32]));
+
+        // The window is fixed by the store, not by whatever maximum age a verifier asks for.
+        store.check_max_age(100).unwrap();
+        assert!(store.check_max_age(101).is_err());
+    }
+}
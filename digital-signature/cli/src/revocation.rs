@@ -0,0 +1,302 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::str::FromStr;
+use std::{fmt, fs, io};
+
+use anyhow::{anyhow, bail, Context};
+use risc0_zkp::core::sha::Digest;
+use risc0_zkvm::Result;
+
+use crate::{format_identity, parse_identity, RotationWithReceipt};
+
+/// Why an identity should no longer be trusted.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Revocation {
+    /// The signer proved they moved to a new identity.
+    Rotated { new_identity: Digest },
+    /// The verifier no longer trusts the identity, e.g. because its passphrase leaked.
+    Revoked,
+}
+
+/// Error for a signature made by an identity on the [RevocationList].
+#[derive(Debug, Eq, PartialEq)]
+pub struct RevokedIdentity {
+    pub identity: Digest,
+    pub revocation: Revocation,
+}
+
+impl fmt::Display for RevokedIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.revocation {
+            Revocation::Rotated { new_identity } => write!(
+                f,
+                "signed by rotated identity {}, which was replaced by {}",
+                format_identity(&self.identity),
+                format_identity(&new_identity)
+            ),
+            Revocation::Revoked => write!(
+                f,
+                "signed by revoked identity {}",
+                format_identity(&self.identity)
+            ),
+        }
+    }
+}
+
+impl std::error::Error for RevokedIdentity {}
+
+/// Local list of identities that signatures should no longer be accepted from.
+///
+/// On disk the list is a text file with one entry per line: `rotated <old> <new>` for an
+/// identity that was rotated, or `revoked <identity>` for one the verifier revoked. Blank lines
+/// and lines starting with `#` are ignored.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct RevocationList {
+    // Keyed by the hex encoding of the identity.
+    entries: BTreeMap<String, Revocation>,
+}
+
+impl RevocationList {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Read a revocation list from `path`. A missing file is read as an empty list.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        match fs::read_to_string(path) {
+            Ok(contents) => contents
+                .parse()
+                .with_context(|| format!("failed to read revocation list {}", path.display())),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Self::new()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        fs::write(path, self.to_string())?;
+        Ok(())
+    }
+
+    /// Verify a rotation receipt and record the link from its old identity to the new one.
+    ///
+    /// If the old identity was already rotated to a different identity, anyone holding its
+    /// passphrase may have made either rotation. The old identity and both new ones are then
+    /// revoked instead.
+    pub fn add_rotation(&mut self, rotation: &RotationWithReceipt) -> Result<()> {
+        let commit = rotation.verify()?;
+        self.insert(
+            commit.old_identity,
+            Revocation::Rotated {
+                new_identity: commit.new_identity,
+            },
+        )
+    }
+
+    pub fn revoke(&mut self, identity: Digest) -> Result<()> {
+        self.insert(identity, Revocation::Revoked)
+    }
+
+    fn insert(&mut self, identity: Digest, revocation: Revocation) -> Result<()> {
+        let key = format_identity(&identity);
+        match (self.entries.get(&key).copied(), revocation) {
+            // Revoking a rotated identity is fine, but a revocation can't be undone by rotating.
+            (Some(Revocation::Revoked), _) => bail!("{} is already revoked", key),
+            (
+                Some(Revocation::Rotated {
+                    new_identity: first,
+                }),
+                Revocation::Rotated {
+                    new_identity: second,
+                },
+            ) => {
+                if first == second {
+                    bail!("{} was already rotated", key);
+                }
+                // There is no telling which rotation the owner made, so trust neither.
+                for conflicting in [first, second] {
+                    self.entries
+                        .insert(format_identity(&conflicting), Revocation::Revoked);
+                }
+                self.entries.insert(key, Revocation::Revoked);
+                return Ok(());
+            }
+            _ => {}
+        }
+        self.entries.insert(key, revocation);
+        Ok(())
+    }
+
+    pub fn get(&self, identity: &Digest) -> Option<Revocation> {
+        self.entries.get(&format_identity(identity)).copied()
+    }
+
+    /// Fail with a [RevokedIdentity] if `identity` was rotated or revoked.
+    pub fn check(&self, identity: &Digest) -> Result<()> {
+        match self.get(identity) {
+            Some(revocation) => Err(RevokedIdentity {
+                identity: *identity,
+                revocation,
+            }
+            .into()),
+            None => Ok(()),
+        }
+    }
+
+    /// Follow rotations from `identity` to the identity that replaced it last. Returns `None` if
+    /// any identity along the way was revoked.
+    pub fn current(&self, identity: &Digest) -> Option<Digest> {
+        let mut current = *identity;
+        // Each identity is rotated at most once, so the chain can't be longer than the list.
+        for _ in 0..=self.entries.len() {
+            match self.get(&current) {
+                None => return Some(current),
+                Some(Revocation::Rotated { new_identity }) => current = new_identity,
+                Some(Revocation::Revoked) => return None,
+            }
+        }
+        None
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Digest, Revocation)> + '_ {
+        self.entries
+            .iter()
+            .map(|(key, revocation)| (parse_identity(key).unwrap(), *revocation))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl FromStr for RevocationList {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut list = RevocationList::new();
+        for (number, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let parsed = match fields.as_slice() {
+                ["rotated", old, new] => parse_identity(old).and_then(|old| {
+                    let new_identity = parse_identity(new)?;
+                    list.insert(old, Revocation::Rotated { new_identity })
+                }),
+                ["revoked", identity] => parse_identity(identity).and_then(|id| list.revoke(id)),
+                _ => Err(anyhow!(
+                    "expected `rotated <old> <new>` or `revoked <identity>`"
+                )),
+            };
+            parsed.with_context(|| format!("line {}", number + 1))?;
+        }
+        Ok(list)
+    }
+}
+
+impl fmt::Display for RevocationList {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (key, revocation) in &self.entries {
+            match revocation {
+                Revocation::Rotated { new_identity } => {
+                    writeln!(f, "rotated {} {}", key, format_identity(new_identity))?
+                }
+                Revocation::Revoked => writeln!(f, "revoked {}", key)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn identity(word: u32) -> Digest {
+        Digest::new([word; 8])
+    }
+
+    #[test]
+    fn text_round_trip() {
+        let mut list = RevocationList::new();
+        list.insert(
+            identity(1),
+            Revocation::Rotated {
+                new_identity: identity(2),
+            },
+        )
+        .unwrap();
+        list.revoke(identity(3)).unwrap();
+        let parsed: RevocationList = list.to_string().parse().unwrap();
+        assert_eq!(parsed, list);
+        assert_eq!(parsed.get(&identity(3)), Some(Revocation::Revoked));
+        assert_eq!(parsed.get(&identity(2)), None);
+        assert!("revoked".parse::<RevocationList>().is_err());
+        assert!("rotated 1234".parse::<RevocationList>().is_err());
+    }
+
+    #[test]
+    fn follows_rotations() {
+        let mut list = RevocationList::new();
+        let rotate = |list: &mut RevocationList, old, new| {
+            list.insert(
+                identity(old),
+                Revocation::Rotated {
+                    new_identity: identity(new),
+                },
+            )
+        };
+        rotate(&mut list, 1, 2).unwrap();
+        rotate(&mut list, 2, 3).unwrap();
+        assert_eq!(list.current(&identity(1)), Some(identity(3)));
+        assert_eq!(list.current(&identity(4)), Some(identity(4)));
+        assert!(rotate(&mut list, 1, 2).is_err());
+
+        let err = list.check(&identity(1)).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<RevokedIdentity>().unwrap().revocation,
+            Revocation::Rotated {
+                new_identity: identity(2)
+            }
+        );
+        assert!(list.check(&identity(3)).is_ok());
+
+        // Revoking the end of the chain leaves no trusted identity, and can't be undone.
+        list.revoke(identity(3)).unwrap();
+        assert_eq!(list.current(&identity(1)), None);
+        assert!(rotate(&mut list, 3, 4).is_err());
+
+        // Rotating the same identity two ways revokes it along with both new identities.
+        rotate(&mut list, 7, 8).unwrap();
+        rotate(&mut list, 7, 9).unwrap();
+        for id in [7, 8, 9] {
+            assert_eq!(list.get(&identity(id)), Some(Revocation::Revoked));
+        }
+        assert_eq!(list.current(&identity(7)), None);
+
+        // A chain that loops back on itself ends nowhere.
+        rotate(&mut list, 5, 6).unwrap();
+        rotate(&mut list, 6, 5).unwrap();
+        assert_eq!(list.current(&identity(5)), None);
+    }
+}
@@ -0,0 +1,69 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use anyhow::{bail, Context};
+use risc0_zkvm::{Receipt, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{HashMode, SignatureWithReceipt};
+
+/// Version of the [SignatureFile] format written by this crate.
+pub const SIGNATURE_FORMAT_VERSION: u32 = 1;
+
+/// Detached signature, as written to disk and sent alongside the message it signs.
+///
+/// The file carries the ID of the method that made the receipt, so that a verifier built from
+/// different guest code turns the signature away up front rather than failing to verify it.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SignatureFile {
+    pub version: u32,
+    pub method_id: Vec<u8>,
+    pub receipt: Receipt,
+}
+
+impl SignatureFile {
+    pub fn new(signature: &SignatureWithReceipt) -> Self {
+        SignatureFile {
+            version: SIGNATURE_FORMAT_VERSION,
+            method_id: signature.mode.method_id().to_vec(),
+            receipt: signature.receipt.clone(),
+        }
+    }
+
+    /// Check the format version and method ID, and take the signature out. The receipt itself is
+    /// not verified.
+    pub fn open(self) -> Result<SignatureWithReceipt> {
+        if self.version != SIGNATURE_FORMAT_VERSION {
+            bail!("unsupported signature format version {}", self.version);
+        }
+        let mode = [HashMode::Host, HashMode::Guest]
+            .into_iter()
+            .find(|mode| mode.method_id() == self.method_id.as_slice());
+        match mode {
+            Some(mode) => Ok(SignatureWithReceipt {
+                receipt: self.receipt,
+                mode,
+            }),
+            None => bail!("signature was made by an unknown build of the signing method"),
+        }
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        bincode::serialize(self).context("failed to encode signature")
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        bincode::deserialize(bytes).context("failed to decode signature")
+    }
+}
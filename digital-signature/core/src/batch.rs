@@ -0,0 +1,193 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Signing many messages in one receipt. The guest commits the root of a Merkle tree over the
+//! message digests, and each message is tied to the batch by an [InclusionProof].
+
+use alloc::vec::Vec;
+
+use risc0_zkp::core::sha::Digest;
+use risc0_zkvm::sha::{sha, Sha};
+use serde::{Deserialize, Serialize};
+
+use crate::{KeyDerivation, Message, Passphrase, SigningContext};
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct BatchSigningRequest {
+    pub passphrase: Passphrase,
+    pub derivation: KeyDerivation,
+    pub context: SigningContext,
+    pub msgs: Vec<Message>,
+}
+
+#[derive(Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct SignBatchCommit {
+    pub identity: Digest,
+    pub derivation: KeyDerivation,
+    pub context: SigningContext,
+    /// Root of the Merkle tree over the signed messages, see [root].
+    pub root: Digest,
+    /// Number of messages in the batch.
+    pub len: u32,
+}
+
+/// Leaves and inner nodes are hashed with different prefixes, so that an inner node can never be
+/// passed off as a signed message.
+fn leaf(msg: &Message) -> Digest {
+    let mut data = [0u8; 33];
+    data[1..].copy_from_slice(&msg.msg);
+    *sha().hash_bytes(&data)
+}
+
+fn node(left: &Digest, right: &Digest) -> Digest {
+    let mut data = [1u8; 65];
+    data[1..33].copy_from_slice(left.as_bytes());
+    data[33..].copy_from_slice(right.as_bytes());
+    *sha().hash_bytes(&data)
+}
+
+/// Hash one level of the tree into the next. A node without a sibling moves up unchanged.
+fn parents(level: &[Digest]) -> Vec<Digest> {
+    level
+        .chunks(2)
+        .map(|pair| match pair {
+            [left, right] => node(left, right),
+            [single] => *single,
+            _ => unreachable!(),
+        })
+        .collect()
+}
+
+/// Merkle root over a non-empty batch of messages.
+pub fn root(msgs: &[Message]) -> Digest {
+    assert!(!msgs.is_empty(), "cannot sign an empty batch");
+    let mut level: Vec<Digest> = msgs.iter().map(leaf).collect();
+    while level.len() > 1 {
+        level = parents(&level);
+    }
+    level[0]
+}
+
+/// Proof that a message is at `index` in a batch of `len` messages.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct InclusionProof {
+    pub index: u32,
+    pub len: u32,
+    /// Siblings on the way from the leaf to the root, skipping levels where the node has none.
+    pub path: Vec<Digest>,
+}
+
+impl InclusionProof {
+    /// Proof for the message at `index` in `msgs`.
+    pub fn new(msgs: &[Message], index: usize) -> Self {
+        assert!(index < msgs.len(), "message {} is not in the batch", index);
+        let mut level: Vec<Digest> = msgs.iter().map(leaf).collect();
+        let mut path = Vec::new();
+        let mut i = index;
+        while level.len() > 1 {
+            if let Some(sibling) = level.get(i ^ 1) {
+                path.push(*sibling);
+            }
+            level = parents(&level);
+            i /= 2;
+        }
+        InclusionProof {
+            index: index as u32,
+            len: msgs.len() as u32,
+            path: path,
+        }
+    }
+
+    /// Root of the batch this proof places `msg` in, or `None` if the proof is malformed.
+    pub fn root(&self, msg: &Message) -> Option<Digest> {
+        if self.index >= self.len {
+            return None;
+        }
+        let mut hash = leaf(msg);
+        let mut path = self.path.iter();
+        let mut index = self.index;
+        let mut width = self.len;
+        while width > 1 {
+            if index % 2 == 1 {
+                hash = node(path.next()?, &hash);
+            } else if index + 1 < width {
+                hash = node(&hash, path.next()?);
+            }
+            index /= 2;
+            width = (width + 1) / 2;
+        }
+        match path.next() {
+            Some(_) => None,
+            None => Some(hash),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn msgs(len: usize) -> Vec<Message> {
+        (0..len).map(|i| Message { msg: [i as u8; 32] }).collect()
+    }
+
+    #[test]
+    fn proofs_reach_root() {
+        for len in 1..=9 {
+            let msgs = msgs(len);
+            let root = root(&msgs);
+            for index in 0..len {
+                let proof = InclusionProof::new(&msgs, index);
+                assert_eq!(proof.root(&msgs[index]), Some(root));
+                if len > 1 {
+                    assert_ne!(proof.root(&msgs[(index + 1) % len]), Some(root));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_proofs() {
+        let msgs = msgs(5);
+        let root = root(&msgs);
+        let proof = InclusionProof::new(&msgs, 2);
+
+        let mut moved = proof.clone();
+        moved.index = 3;
+        assert_ne!(moved.root(&msgs[2]), Some(root));
+
+        let mut short = proof.clone();
+        short.path.pop();
+        assert_eq!(short.root(&msgs[2]), None);
+
+        let mut long = proof.clone();
+        long.path.push(root);
+        assert_eq!(long.root(&msgs[2]), None);
+
+        let mut outside = proof;
+        outside.index = 5;
+        assert_eq!(outside.root(&msgs[2]), None);
+    }
+
+    #[test]
+    fn inner_nodes_are_not_messages() {
+        let msgs = msgs(4);
+        let mut inner = Message { msg: [0; 32] };
+        inner
+            .msg
+            .copy_from_slice(node(&leaf(&msgs[0]), &leaf(&msgs[1])).as_bytes());
+        let pair = [inner, msgs[2].clone(), msgs[3].clone()];
+        assert_ne!(root(&pair), root(&msgs));
+    }
+}
@@ -14,9 +14,19 @@
 
 #![cfg_attr(not(test), no_std)]
 
+extern crate alloc;
+
+pub mod batch;
+pub mod threshold;
+
+use alloc::string::String;
+use alloc::vec::Vec;
 use core::fmt::{Debug, Display, Formatter};
+use core::str::FromStr;
 
 use risc0_zkp::core::sha::Digest;
+use risc0_zkvm::serde::to_vec;
+use risc0_zkvm::sha::{sha, Sha};
 use serde::{Deserialize, Serialize};
 
 #[derive(Clone, Deserialize, Eq, PartialEq, Serialize)]
@@ -24,6 +34,15 @@ pub struct Message {
     pub msg: [u8; 32],
 }
 
+impl Message {
+    /// The message for a SHA-256 digest.
+    pub fn from_digest(digest: &Digest) -> Self {
+        let mut msg = [0u8; 32];
+        msg.copy_from_slice(digest.as_bytes());
+        Message { msg }
+    }
+}
+
 impl Display for Message {
     fn fmt(&self, f: &mut Formatter) -> core::fmt::Result {
         for word in self.msg {
@@ -42,19 +61,180 @@ impl Debug for Message {
     }
 }
 
+/// Error parsing a [Message] from hex.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ParseMessageError {
+    /// The string was not 64 characters long.
+    Length(usize),
+    /// The string contained a character that is not a hex digit.
+    InvalidDigit,
+}
+
+impl Display for ParseMessageError {
+    fn fmt(&self, f: &mut Formatter) -> core::fmt::Result {
+        match self {
+            ParseMessageError::Length(len) => {
+                core::write!(f, "message must be 64 hex digits, not {}", len)
+            }
+            ParseMessageError::InvalidDigit => core::write!(f, "message is not valid hex"),
+        }
+    }
+}
+
+/// Parses the hex encoding written by [Display].
+impl FromStr for Message {
+    type Err = ParseMessageError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.len() != 64 {
+            return Err(ParseMessageError::Length(s.len()));
+        }
+        if !s.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(ParseMessageError::InvalidDigit);
+        }
+        let mut msg = [0u8; 32];
+        for (byte, pair) in msg.iter_mut().zip(s.as_bytes().chunks_exact(2)) {
+            let pair = core::str::from_utf8(pair).unwrap();
+            *byte = u8::from_str_radix(pair, 16).unwrap();
+        }
+        Ok(Message { msg })
+    }
+}
+
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
 pub struct Passphrase {
     pub pass: [u8; 32],
 }
 
+/// Iteration count used for stretched identities unless the signer picks another.
+pub const DEFAULT_ITERATIONS: u32 = 1024;
+
+/// How a signer's identity is derived from their passphrase.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum KeyDerivation {
+    /// The identity is a single hash of the passphrase hash. Identities made this way can be
+    /// matched against a dictionary of likely passphrases, so this is only kept so that existing
+    /// identities stay verifiable.
+    Legacy,
+    /// The passphrase hash is stretched with the salt over the given number of iterations, and
+    /// the identity is the hash of the result. See [stretch].
+    Stretched { salt: [u8; 32], iterations: u32 },
+}
+
+impl KeyDerivation {
+    /// The identity this derivation gives a passphrase. A legacy identity is hashed the same way
+    /// as `risc0_zkvm::guest::sha::digest` hashes the passphrase, so existing identities keep
+    /// their value.
+    pub fn identity(&self, pass: &Passphrase) -> Digest {
+        match self {
+            KeyDerivation::Legacy => *sha().hash_words(&to_vec(&pass.pass).unwrap()),
+            KeyDerivation::Stretched { salt, iterations } => stretch(pass, salt, *iterations),
+        }
+    }
+}
+
+/// Stretch a passphrase into an identity, PBKDF2-style: the first block hashes the passphrase
+/// with the salt, every later block hashes the passphrase with the block before it, and the
+/// blocks are XORed together. The identity is the SHA-256 of that key.
+pub fn stretch(pass: &Passphrase, salt: &[u8; 32], iterations: u32) -> Digest {
+    assert!(
+        iterations > 0,
+        "key derivation needs at least one iteration"
+    );
+    let mut block = [0u8; 68];
+    block[..32].copy_from_slice(&pass.pass);
+    block[32..64].copy_from_slice(salt);
+    block[64..].copy_from_slice(&1u32.to_be_bytes());
+    let mut u = *sha().hash_bytes(&block);
+    let mut key = [0u8; 32];
+    key.copy_from_slice(u.as_bytes());
+    for _ in 1..iterations {
+        let mut block = [0u8; 64];
+        block[..32].copy_from_slice(&pass.pass);
+        block[32..].copy_from_slice(u.as_bytes());
+        u = *sha().hash_bytes(&block);
+        for (k, b) in key.iter_mut().zip(u.as_bytes()) {
+            *k ^= b;
+        }
+    }
+    *sha().hash_bytes(&key)
+}
+
+/// Where and when a signature was made. The guest commits it as given, so that verifiers can
+/// refuse signatures meant for another application, made too long ago, or seen before.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct SigningContext {
+    /// Tag naming the application or purpose the signature is for.
+    pub domain: String,
+    /// Random value that makes every signature unique, so replays can be spotted.
+    pub nonce: [u8; 32],
+    /// Seconds since the UNIX epoch, as reported by the signer's host.
+    pub timestamp: u64,
+}
+
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
 pub struct SigningRequest {
     pub passphrase: Passphrase,
+    pub derivation: KeyDerivation,
+    pub context: SigningContext,
     pub msg: Message,
 }
 
+/// Request to sign a whole message, which the guest hashes itself. Unlike a [SigningRequest],
+/// this ties the signature to the content of the message and not just to a digest the host
+/// claims to have computed.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct PlaintextSigningRequest {
+    pub passphrase: Passphrase,
+    pub derivation: KeyDerivation,
+    pub context: SigningContext,
+    pub msg: Vec<u8>,
+}
+
 #[derive(Debug, Deserialize, Eq, PartialEq, Serialize)]
 pub struct SignMessageCommit {
     pub identity: Digest,
+    pub derivation: KeyDerivation,
+    pub context: SigningContext,
     pub msg: Message,
 }
+
+/// Request to link an identity to a new one, by proving knowledge of both passphrases.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct RotationRequest {
+    pub old_passphrase: Passphrase,
+    pub old_derivation: KeyDerivation,
+    pub new_passphrase: Passphrase,
+    pub new_derivation: KeyDerivation,
+    pub context: SigningContext,
+}
+
+#[derive(Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct RotateCommit {
+    pub old_identity: Digest,
+    pub new_identity: Digest,
+    pub new_derivation: KeyDerivation,
+    pub context: SigningContext,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn message_hex_round_trip() {
+        let mut msg = Message { msg: [0; 32] };
+        for (i, byte) in msg.msg.iter_mut().enumerate() {
+            *byte = (i * 37) as u8;
+        }
+        let hex = msg.to_string();
+        assert_eq!(hex.len(), 64);
+        assert_eq!(hex.parse::<Message>(), Ok(msg.clone()));
+        assert_eq!(hex.to_uppercase().parse::<Message>(), Ok(msg));
+
+        assert_eq!("abcd".parse::<Message>(), Err(ParseMessageError::Length(4)));
+        let bad = format!("+1{}", &hex[2..]);
+        assert_eq!(bad.parse::<Message>(), Err(ParseMessageError::InvalidDigit));
+    }
+}
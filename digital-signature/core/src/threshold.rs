@@ -0,0 +1,112 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! k-of-n signatures. The guest checks that at least `threshold` distinct members of a signer set
+//! know their passphrases, and commits the digest of the set without revealing which members
+//! signed.
+
+use alloc::vec::Vec;
+
+use risc0_zkp::core::sha::Digest;
+use risc0_zkvm::sha::{sha, Sha};
+use serde::{Deserialize, Serialize};
+
+use crate::{KeyDerivation, Message, Passphrase, SigningContext};
+
+/// The identities allowed to take part in a threshold signature, kept sorted so that the same
+/// members always give the same digest.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct SignerSet {
+    identities: Vec<Digest>,
+}
+
+impl SignerSet {
+    pub fn new(mut identities: Vec<Digest>) -> Self {
+        identities.sort_by(|a, b| a.as_bytes().cmp(b.as_bytes()));
+        identities.dedup();
+        SignerSet {
+            identities: identities,
+        }
+    }
+
+    pub fn identities(&self) -> &[Digest] {
+        &self.identities
+    }
+
+    pub fn len(&self) -> usize {
+        self.identities.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.identities.is_empty()
+    }
+
+    pub fn contains(&self, identity: &Digest) -> bool {
+        self.identities.contains(identity)
+    }
+
+    /// SHA-256 over the concatenated identities.
+    pub fn digest(&self) -> Digest {
+        let mut data = Vec::with_capacity(32 * self.identities.len());
+        for identity in &self.identities {
+            data.extend_from_slice(identity.as_bytes());
+        }
+        *sha().hash_bytes(&data)
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Signer {
+    pub passphrase: Passphrase,
+    pub derivation: KeyDerivation,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct ThresholdSigningRequest {
+    pub signers: Vec<Signer>,
+    pub signer_set: SignerSet,
+    pub threshold: u32,
+    pub context: SigningContext,
+    pub msg: Message,
+}
+
+#[derive(Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct SignThresholdCommit {
+    pub signer_set: Digest,
+    pub threshold: u32,
+    pub context: SigningContext,
+    pub msg: Message,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn identity(word: u32) -> Digest {
+        Digest::new([word; 8])
+    }
+
+    #[test]
+    fn digest_ignores_order_and_duplicates() {
+        let set = SignerSet::new(vec![identity(1), identity(2), identity(3)]);
+        let shuffled = SignerSet::new(vec![identity(3), identity(1), identity(2), identity(1)]);
+        assert_eq!(shuffled, set);
+        assert_eq!(shuffled.digest(), set.digest());
+        assert_eq!(shuffled.len(), 3);
+
+        let smaller = SignerSet::new(vec![identity(1), identity(2)]);
+        assert_ne!(smaller.digest(), set.digest());
+        assert!(set.contains(&identity(3)) && !smaller.contains(&identity(3)));
+    }
+}
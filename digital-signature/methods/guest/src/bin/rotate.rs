@@ -0,0 +1,34 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![no_main]
+#![no_std]
+
+use risc0_zkvm::guest::env;
+use digital_signature_core::{RotateCommit, RotationRequest};
+
+risc0_zkvm::guest::entry!(main);
+
+pub fn main() {
+    let request: RotationRequest = env::read();
+    let old_identity = request.old_derivation.identity(&request.old_passphrase);
+    let new_identity = request.new_derivation.identity(&request.new_passphrase);
+    assert!(old_identity != new_identity, "rotation must change the identity");
+    env::commit(&RotateCommit {
+        old_identity: old_identity,
+        new_identity: new_identity,
+        new_derivation: request.new_derivation,
+        context: request.context,
+    });
+}
@@ -15,15 +15,18 @@
 #![no_main]
 #![no_std]
 
-use risc0_zkvm::guest::{env, sha};
+use risc0_zkvm::guest::env;
 use digital_signature_core::{SignMessageCommit, SigningRequest};
 
 risc0_zkvm::guest::entry!(main);
 
 pub fn main() {
     let request: SigningRequest = env::read();
+    let identity = request.derivation.identity(&request.passphrase);
     env::commit(&SignMessageCommit {
-        identity: *sha::digest(&request.passphrase.pass),
+        identity: identity,
+        derivation: request.derivation,
+        context: request.context,
         msg: request.msg,
     });
 }
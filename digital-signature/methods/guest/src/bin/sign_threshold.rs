@@ -0,0 +1,52 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![no_main]
+#![no_std]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+
+use risc0_zkvm::guest::env;
+use risc0_zkvm::sha::Digest;
+use digital_signature_core::threshold::{SignThresholdCommit, ThresholdSigningRequest};
+
+risc0_zkvm::guest::entry!(main);
+
+pub fn main() {
+    let request: ThresholdSigningRequest = env::read();
+    let threshold = request.threshold as usize;
+    assert!(threshold > 0, "threshold must be at least 1");
+    assert!(
+        threshold <= request.signer_set.len(),
+        "threshold is larger than the signer set"
+    );
+
+    let mut signed: Vec<Digest> = Vec::new();
+    for signer in &request.signers {
+        let identity = signer.derivation.identity(&signer.passphrase);
+        assert!(request.signer_set.contains(&identity), "signer is not in the signer set");
+        assert!(!signed.contains(&identity), "signer appears more than once");
+        signed.push(identity);
+    }
+    assert!(signed.len() >= threshold, "not enough signers");
+
+    env::commit(&SignThresholdCommit {
+        signer_set: request.signer_set.digest(),
+        threshold: request.threshold,
+        context: request.context,
+        msg: request.msg,
+    });
+}
@@ -0,0 +1,94 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use serde::{Deserialize, Serialize};
+
+/// How a frozen election decides a motion, fixed when the election is initialized.
+///
+/// The motion is carried if enough votes were counted to make quorum, and strictly more than
+/// `numerator / denominator` of them were for the motion's option. In a weighted election both
+/// thresholds apply to the total weight of the counted ballots.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct DecisionRule {
+    /// Option that votes for the motion are cast for.
+    pub option: u32,
+    /// Least number of counted votes for the result to stand.
+    pub quorum: u32,
+    pub numerator: u32,
+    pub denominator: u32,
+}
+
+/// Verdict on a motion, as proven by the freeze method.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum Outcome {
+    Passed,
+    Failed,
+    /// Too few votes were counted for the result to stand.
+    NoQuorum,
+}
+
+impl DecisionRule {
+    pub fn new(option: u32, quorum: u32, numerator: u32, denominator: u32) -> Self {
+        assert!(
+            numerator < denominator,
+            "majority threshold must be less than one"
+        );
+        DecisionRule {
+            option: option,
+            quorum: quorum,
+            numerator: numerator,
+            denominator: denominator,
+        }
+    }
+
+    /// A rule that needs more than half of the counted votes to be for `option`.
+    pub fn simple_majority(option: u32, quorum: u32) -> Self {
+        DecisionRule::new(option, quorum, 1, 2)
+    }
+
+    pub fn decide(&self, tally: &[u32]) -> Outcome {
+        let total: u64 = tally.iter().map(|count| *count as u64).sum();
+        let support = tally.get(self.option as usize).copied().unwrap_or(0) as u64;
+        if total < self.quorum as u64 {
+            Outcome::NoQuorum
+        } else if support * self.denominator as u64 > total * self.numerator as u64 {
+            Outcome::Passed
+        } else {
+            Outcome::Failed
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn simple_majority_needs_more_than_half() {
+        let rule = DecisionRule::simple_majority(1, 4);
+        assert_eq!(rule.decide(&[1, 2]), Outcome::NoQuorum);
+        assert_eq!(rule.decide(&[2, 2]), Outcome::Failed);
+        assert_eq!(rule.decide(&[2, 3]), Outcome::Passed);
+    }
+
+    #[test]
+    fn supermajority_counts_every_option() {
+        let rule = DecisionRule::new(0, 0, 2, 3);
+        // Votes for other options count toward the total, and so against the motion.
+        assert_eq!(rule.decide(&[20, 5, 5]), Outcome::Failed);
+        assert_eq!(rule.decide(&[21, 5, 5]), Outcome::Passed);
+        assert_eq!(rule.decide(&[0, 0, 0]), Outcome::Failed);
+        assert_eq!(rule.decide(&[u32::MAX, u32::MAX, 0]), Outcome::Failed);
+    }
+}
@@ -14,24 +14,212 @@
 
 #![cfg_attr(not(test), no_std)]
 
-use risc0_zkp::core::sha::Digest;
+extern crate alloc;
+
+pub mod decision;
+pub mod merkle;
+pub mod registry;
+pub mod round;
+pub mod secret;
+pub mod tally;
+pub mod weights;
+
+#[cfg(test)]
+mod simulation;
+
+use alloc::vec::Vec;
+
+use risc0_zkp::core::sha::{Digest, Sha};
+use risc0_zkvm::serde::to_vec;
+use risc0_zkvm::sha::sha;
 use serde::{Deserialize, Serialize};
 
+pub use crate::decision::{DecisionRule, Outcome};
+pub use crate::merkle::{MerkleProof, VoterProof, VoterSet};
+pub use crate::registry::{Passphrase, VoterRegistry};
+pub use crate::round::{NewRoundCommit, NewRoundParams, NewRoundResult};
+pub use crate::secret::{
+    SubmitSecretBallotCommit, SubmitSecretBallotParams, SubmitSecretBallotResult,
+};
+pub use crate::tally::{TallyCommit, TallyParams, TallyResult};
+pub use crate::weights::{WeightProof, WeightTable};
+
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
 pub struct VotingMachineState {
     pub polls_open: bool,
-    pub voter_bitfield: u32,
-    pub count: u32,
+    /// Root of the [VoterSet] recording which voters have already voted.
+    pub voter_root: Digest,
+    /// Number of first-choice votes for each option. Its length is the number of options.
+    pub tally: Vec<u32>,
+    /// Every counted ranked ballot, kept so they can be re-tallied by instant-runoff.
+    pub rankings: Vec<Vec<u32>>,
+    /// Secret salt for a secret-ballot election, or `None` if ballots are public.
+    ///
+    /// Mixing a secret into the state keeps anyone from working out how a voter voted by guessing
+    /// at the state behind a published state digest.
+    pub ballot_salt: Option<Digest>,
+    /// Root of the [VoterRegistry] for an election that authenticates voters, or `None` if any
+    /// voter index may vote.
+    pub registry_root: Option<Digest>,
+    /// Root of the [WeightTable] for a weighted election, or `None` if every ballot counts once.
+    pub weight_root: Option<Digest>,
+    /// When ballots may be cast, or `None` if the polls stay open until they are frozen.
+    pub window: Option<PollWindow>,
+    /// How the election decides its motion when frozen, or `None` if it only reports a tally.
+    pub decision_rule: Option<DecisionRule>,
+    /// Number of the current round, counting from zero for the round started by init.
+    pub round: u32,
+    /// Digest of the frozen state the current round was started from, or `None` in the first
+    /// round.
+    pub previous_round: Option<Digest>,
+}
+
+/// Period during which an election accepts ballots, in seconds since the Unix epoch.
+///
+/// The time of each ballot is supplied by the host, so the window only holds a polling station to
+/// the times it claims. Every ballot receipt commits to its time so that the claims can be
+/// checked against when the receipts were actually published.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct PollWindow {
+    pub opens: u64,
+    pub closes: u64,
+}
+
+impl PollWindow {
+    pub fn new(opens: u64, closes: u64) -> Self {
+        assert!(opens < closes, "polls must open before they close");
+        PollWindow {
+            opens: opens,
+            closes: closes,
+        }
+    }
+
+    /// Whether ballots may be cast at `time`. The window includes its opening time but not its
+    /// closing time.
+    pub fn contains(&self, time: u64) -> bool {
+        self.opens <= time && time < self.closes
+    }
 }
 
 impl VotingMachineState {
-    pub fn vote(&mut self, voter: u32, vote_yes: bool) -> bool {
+    /// Create a state for a fresh election with the given number of options.
+    pub fn new(options: u32, voter_root: Digest) -> Self {
+        VotingMachineState {
+            polls_open: true,
+            voter_root,
+            tally: alloc::vec![0; options as usize],
+            rankings: Vec::new(),
+            ballot_salt: None,
+            registry_root: None,
+            weight_root: None,
+            window: None,
+            decision_rule: None,
+            round: 0,
+            previous_round: None,
+        }
+    }
+
+    /// Create a state for a fresh secret-ballot election. `ballot_salt` must be random and must
+    /// never be published.
+    pub fn new_secret(options: u32, voter_root: Digest, ballot_salt: Digest) -> Self {
+        VotingMachineState {
+            ballot_salt: Some(ballot_salt),
+            ..VotingMachineState::new(options, voter_root)
+        }
+    }
+
+    pub fn is_secret_ballot(&self) -> bool {
+        self.ballot_salt.is_some()
+    }
+
+    pub fn is_weighted(&self) -> bool {
+        self.weight_root.is_some()
+    }
+
+    /// The weight a ballot from `voter` counts for, using `weight_proof` to look it up. Every
+    /// voter weighs one in an unweighted election.
+    ///
+    /// Panics if the election is weighted and `weight_proof` is missing or does not belong to
+    /// its weight table, so a host cannot drop a ballot by supplying a bad proof.
+    pub fn weight(&self, voter: u32, weight_proof: Option<&WeightProof>) -> u32 {
+        match &self.weight_root {
+            None => 1,
+            Some(root) => {
+                let proof = weight_proof.expect("weighted election needs a weight proof");
+                assert_eq!(
+                    &proof.root(voter),
+                    root,
+                    "weight proof does not match the weight table root"
+                );
+                proof.weight
+            }
+        }
+    }
+
+    /// The outcome of the election's motion under its decision rule, given the votes so far.
+    pub fn outcome(&self) -> Option<Outcome> {
+        self.decision_rule.map(|rule| rule.decide(&self.tally))
+    }
+
+    /// Whether the machine accepts ballots cast at `time`.
+    pub fn is_accepting(&self, time: u64) -> bool {
+        self.polls_open && !matches!(self.window, Some(window) if !window.contains(time))
+    }
+
+    /// Digest of the state, as committed by the guests in their `state`, `old_state` and
+    /// `new_state` fields.
+    pub fn digest(&self) -> Digest {
+        *sha().hash_words(&to_vec(self).unwrap())
+    }
+
+    /// Check that the ballot carries the passphrase for its voter's registered identity, using
+    /// `registry_proof` to look the identity up. Always true for elections without a registry.
+    pub fn is_authenticated(&self, ballot: &Ballot, registry_proof: Option<&MerkleProof>) -> bool {
+        match &self.registry_root {
+            None => true,
+            Some(root) => match (&ballot.passphrase, registry_proof) {
+                (Some(passphrase), Some(proof)) => {
+                    &proof.root(ballot.voter, &passphrase.identity()) == root
+                }
+                _ => false,
+            },
+        }
+    }
+
+    /// Record a ballot cast at `time`, using `voter_proof` to show whether its voter has voted
+    /// before.
+    ///
+    /// Panics if `voter_proof` does not belong to the current voter set, so a host cannot drop a
+    /// ballot by supplying a bad proof, and likewise for `weight_proof` in a weighted election.
+    /// Ballots that fail authentication, that are cast outside the poll window, or whose voter
+    /// has no weight are not counted. Weighted elections only take single-choice votes, since
+    /// instant-runoff counts every ranking once.
+    pub fn vote(
+        &mut self,
+        ballot: &Ballot,
+        voter_proof: &VoterProof,
+        registry_proof: Option<&MerkleProof>,
+        weight_proof: Option<&WeightProof>,
+        time: u64,
+    ) -> bool {
         let mut vote_counted = false;
-        let voter_mask = 1 << voter;
-        if self.polls_open && 0 == self.voter_bitfield & voter_mask {
-            self.voter_bitfield |= voter_mask;
-            if vote_yes {
-                self.count += 1;
+        let has_voted = voter_proof
+            .has_voted(&self.voter_root, ballot.voter)
+            .expect("voter proof does not match the voter set root");
+        let weight = self.weight(ballot.voter, weight_proof);
+        let ranked = matches!(ballot.vote, Vote::Ranked(_));
+        if self.is_accepting(time)
+            && !has_voted
+            && weight > 0
+            && !(ranked && self.is_weighted())
+            && ballot.vote.is_valid(self.tally.len())
+            && self.is_authenticated(ballot, registry_proof)
+        {
+            self.voter_root = voter_proof.root(ballot.voter, true);
+            let count = &mut self.tally[ballot.vote.first_choice() as usize];
+            *count = count.checked_add(weight).expect("tally overflowed");
+            if let Vote::Ranked(ranking) = &ballot.vote {
+                self.rankings.push(ranking.clone());
             }
             vote_counted = true
         }
@@ -42,14 +230,53 @@ impl VotingMachineState {
 #[derive(Debug, Deserialize, Eq, PartialEq, Serialize)]
 pub struct InitializeVotingMachineCommit {
     pub polls_open: bool,
-    pub voter_bitfield: u32,
+    pub voter_root: Digest,
+    pub tally: Vec<u32>,
+    pub secret_ballot: bool,
+    pub registry_root: Option<Digest>,
+    pub weight_root: Option<Digest>,
+    pub window: Option<PollWindow>,
+    pub decision_rule: Option<DecisionRule>,
     pub state: Digest,
 }
 
+/// The choice made on a ballot. Options are numbered from zero.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum Vote {
+    /// A vote for a single option.
+    Choice(u32),
+    /// Options in order of preference, most preferred first. Options left off are not ranked.
+    Ranked(Vec<u32>),
+}
+
+impl Vote {
+    /// Check that the vote only names existing options, and names each at most once.
+    pub fn is_valid(&self, options: usize) -> bool {
+        match self {
+            Vote::Choice(option) => (*option as usize) < options,
+            Vote::Ranked(ranking) => {
+                !ranking.is_empty()
+                    && ranking.iter().enumerate().all(|(i, option)| {
+                        (*option as usize) < options && !ranking[..i].contains(option)
+                    })
+            }
+        }
+    }
+
+    pub fn first_choice(&self) -> u32 {
+        match self {
+            Vote::Choice(option) => *option,
+            Vote::Ranked(ranking) => ranking[0],
+        }
+    }
+}
+
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
 pub struct Ballot {
     pub voter: u32,
-    pub vote_yes: bool,
+    pub vote: Vote,
+    /// The voter's passphrase, needed in elections with a voter registry. Never committed.
+    pub passphrase: Option<Passphrase>,
 }
 
 #[derive(Debug, Deserialize, Eq, PartialEq, Serialize)]
@@ -57,9 +284,13 @@ pub struct SubmitBallotCommit {
     pub old_state: Digest,
     pub new_state: Digest,
     pub polls_open: bool,
-    pub voter_bitfield: u32,
+    pub voter_root: Digest,
     pub voter: u32,
-    pub vote_yes: bool,
+    pub vote: Vote,
+    /// Weight of the voter, which is always one in an unweighted election.
+    pub weight: u32,
+    /// Time the host says the ballot was cast at.
+    pub time: u64,
     pub vote_counted: bool,
 }
 
@@ -67,30 +298,154 @@ pub struct SubmitBallotCommit {
 pub struct SubmitBallotParams {
     pub state: VotingMachineState,
     pub ballot: Ballot,
+    pub voter_proof: VoterProof,
+    pub registry_proof: Option<MerkleProof>,
+    pub weight_proof: Option<WeightProof>,
+    /// Time the ballot was cast at, in seconds since the Unix epoch.
+    pub time: u64,
 }
 
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
 pub struct SubmitBallotResult {
     pub state: VotingMachineState,
     pub vote_counted: bool,
-    pub vote_yes: bool,
 }
 
 impl SubmitBallotParams {
-    pub fn new(state: VotingMachineState, ballot: Ballot) -> Self {
+    pub fn new(
+        state: VotingMachineState,
+        ballot: Ballot,
+        voter_proof: VoterProof,
+        registry_proof: Option<MerkleProof>,
+        weight_proof: Option<WeightProof>,
+        time: u64,
+    ) -> Self {
         SubmitBallotParams {
             state: state,
             ballot: ballot,
+            voter_proof: voter_proof,
+            registry_proof: registry_proof,
+            weight_proof: weight_proof,
+            time: time,
         }
     }
 
     pub fn process(&self) -> SubmitBallotResult {
+        assert!(
+            !self.state.is_secret_ballot(),
+            "secret ballots must be submitted with SubmitSecretBallotParams"
+        );
         let mut state = self.state.clone();
-        let vote_counted = state.vote(self.ballot.voter, self.ballot.vote_yes);
+        let vote_counted = state.vote(
+            &self.ballot,
+            &self.voter_proof,
+            self.registry_proof.as_ref(),
+            self.weight_proof.as_ref(),
+            self.time,
+        );
         SubmitBallotResult {
             state: state,
             vote_counted: vote_counted,
-            vote_yes: self.ballot.vote_yes,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct SubmitBatchCommit {
+    pub old_state: Digest,
+    pub new_state: Digest,
+    pub polls_open: bool,
+    pub voter_root: Digest,
+    pub voters: Vec<u32>,
+    pub votes: Vec<Vote>,
+    /// Weight of each voter, which is always one in an unweighted election.
+    pub weights: Vec<u32>,
+    /// Time the host says the batch was cast at.
+    pub time: u64,
+    /// Whether each ballot was counted, in the order they were submitted.
+    pub vote_counted: Vec<bool>,
+}
+
+/// Parameters for applying several ballots in one execution. All the ballots are treated as cast
+/// at the same time.
+///
+/// Each voter proof must be made against the voter set as it stands after the ballots before it
+/// have been applied.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct SubmitBatchParams {
+    pub state: VotingMachineState,
+    pub ballots: Vec<Ballot>,
+    pub voter_proofs: Vec<VoterProof>,
+    pub registry_proofs: Vec<Option<MerkleProof>>,
+    pub weight_proofs: Vec<Option<WeightProof>>,
+    pub time: u64,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct SubmitBatchResult {
+    pub state: VotingMachineState,
+    pub vote_counted: Vec<bool>,
+}
+
+impl SubmitBatchParams {
+    pub fn new(
+        state: VotingMachineState,
+        ballots: Vec<Ballot>,
+        voter_proofs: Vec<VoterProof>,
+        registry_proofs: Vec<Option<MerkleProof>>,
+        weight_proofs: Vec<Option<WeightProof>>,
+        time: u64,
+    ) -> Self {
+        SubmitBatchParams {
+            state: state,
+            ballots: ballots,
+            voter_proofs: voter_proofs,
+            registry_proofs: registry_proofs,
+            weight_proofs: weight_proofs,
+            time: time,
+        }
+    }
+
+    pub fn process(&self) -> SubmitBatchResult {
+        assert!(
+            !self.state.is_secret_ballot(),
+            "secret ballots must be submitted with SubmitSecretBallotParams"
+        );
+        assert_eq!(
+            self.ballots.len(),
+            self.voter_proofs.len(),
+            "every ballot needs a voter proof"
+        );
+        assert_eq!(
+            self.ballots.len(),
+            self.registry_proofs.len(),
+            "every ballot needs a registry proof entry"
+        );
+        assert_eq!(
+            self.ballots.len(),
+            self.weight_proofs.len(),
+            "every ballot needs a weight proof entry"
+        );
+        let mut state = self.state.clone();
+        let vote_counted = self
+            .ballots
+            .iter()
+            .zip(self.voter_proofs.iter())
+            .zip(self.registry_proofs.iter())
+            .zip(self.weight_proofs.iter())
+            .map(|(((ballot, voter_proof), registry_proof), weight_proof)| {
+                state.vote(
+                    ballot,
+                    voter_proof,
+                    registry_proof.as_ref(),
+                    weight_proof.as_ref(),
+                    self.time,
+                )
+            })
+            .collect();
+        SubmitBatchResult {
+            state: state,
+            vote_counted: vote_counted,
         }
     }
 }
@@ -100,8 +455,11 @@ pub struct FreezeVotingMachineCommit {
     pub old_state: Digest,
     pub new_state: Digest,
     pub polls_open: bool,
-    pub voter_bitfield: u32,
-    pub count: u32,
+    pub voter_root: Digest,
+    pub tally: Vec<u32>,
+    /// The rule fixed at init, and the outcome it gives for the final tally.
+    pub decision_rule: Option<DecisionRule>,
+    pub outcome: Option<Outcome>,
 }
 
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
@@ -125,3 +483,210 @@ impl FreezeVotingMachineParams {
         FreezeVotingMachineResult { state: state }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vote(state: &mut VotingMachineState, voters: &mut VoterSet, voter: u32, vote: Vote) -> bool {
+        let ballot = Ballot {
+            voter,
+            vote,
+            passphrase: None,
+        };
+        let vote_counted = state.vote(&ballot, &voters.prove(voter), None, None, 0);
+        if vote_counted {
+            voters.insert(voter);
+        }
+        vote_counted
+    }
+
+    #[test]
+    fn votes_are_tallied_per_option() {
+        let mut voters = VoterSet::new();
+        let mut state = VotingMachineState::new(3, voters.root());
+        assert!(vote(&mut state, &mut voters, 0, Vote::Choice(2)));
+        assert!(vote(&mut state, &mut voters, 1, Vote::Ranked(vec![0, 2])));
+        assert!(vote(&mut state, &mut voters, 2, Vote::Choice(2)));
+        assert_eq!(state.tally, vec![1, 0, 2]);
+        assert_eq!(state.rankings, vec![vec![0, 2]]);
+    }
+
+    #[test]
+    fn batch_matches_individual_submissions() {
+        let ballots = vec![
+            Ballot {
+                voter: 0,
+                vote: Vote::Choice(1),
+                passphrase: None,
+            },
+            Ballot {
+                voter: 70_000,
+                vote: Vote::Choice(0),
+                passphrase: None,
+            },
+            Ballot {
+                voter: 0,
+                vote: Vote::Choice(0),
+                passphrase: None,
+            },
+            Ballot {
+                voter: 1,
+                vote: Vote::Choice(2),
+                passphrase: None,
+            },
+        ];
+
+        let mut voters = VoterSet::new();
+        let initial_state = VotingMachineState::new(2, voters.root());
+        let mut state = initial_state.clone();
+        let mut voter_proofs = Vec::new();
+        let mut expected_counted = Vec::new();
+        for ballot in ballots.iter() {
+            voter_proofs.push(voters.prove(ballot.voter));
+            expected_counted.push(vote(
+                &mut state,
+                &mut voters,
+                ballot.voter,
+                ballot.vote.clone(),
+            ));
+        }
+
+        let registry_proofs = vec![None; ballots.len()];
+        let weight_proofs = vec![None; ballots.len()];
+        let result = SubmitBatchParams::new(
+            initial_state,
+            ballots,
+            voter_proofs,
+            registry_proofs,
+            weight_proofs,
+            0,
+        )
+        .process();
+        assert_eq!(result.vote_counted, vec![true, true, false, false]);
+        assert_eq!(result.vote_counted, expected_counted);
+        assert_eq!(result.state, state);
+    }
+
+    #[test]
+    fn invalid_votes_are_not_counted() {
+        let mut voters = VoterSet::new();
+        let mut state = VotingMachineState::new(3, voters.root());
+        assert!(!vote(&mut state, &mut voters, 0, Vote::Choice(3)));
+        assert!(!vote(&mut state, &mut voters, 0, Vote::Ranked(vec![])));
+        assert!(!vote(&mut state, &mut voters, 0, Vote::Ranked(vec![1, 1])));
+        assert!(!vote(&mut state, &mut voters, 0, Vote::Ranked(vec![1, 5])));
+        assert_eq!(state.tally, vec![0, 0, 0]);
+        assert!(!voters.has_voted(0));
+        assert!(vote(&mut state, &mut voters, 0, Vote::Choice(1)));
+    }
+
+    #[test]
+    fn registered_voters_must_authenticate() {
+        let passphrase = Passphrase { pass: [1; 32] };
+        let mut registry = VoterRegistry::new();
+        registry.register(5, passphrase.identity());
+        let voters = VoterSet::new();
+        let mut state = VotingMachineState::new(2, voters.root());
+        state.registry_root = Some(registry.root());
+
+        let mut ballot = Ballot {
+            voter: 5,
+            vote: Vote::Choice(0),
+            passphrase: None,
+        };
+        let registry_proof = registry.prove(5);
+        assert!(!state.is_authenticated(&ballot, Some(&registry_proof)));
+        ballot.passphrase = Some(Passphrase { pass: [2; 32] });
+        assert!(!state.is_authenticated(&ballot, Some(&registry_proof)));
+        ballot.passphrase = Some(passphrase.clone());
+        assert!(!state.is_authenticated(&ballot, None));
+        assert!(state.is_authenticated(&ballot, Some(&registry_proof)));
+
+        // The right passphrase does not let someone vote as a different voter.
+        ballot.voter = 6;
+        assert!(!state.is_authenticated(&ballot, Some(&registry.prove(6))));
+
+        ballot.voter = 5;
+        assert!(state.vote(&ballot, &voters.prove(5), Some(&registry_proof), None, 0));
+        assert_eq!(state.tally, vec![1, 0]);
+    }
+
+    #[test]
+    fn ballots_outside_the_window_are_not_counted() {
+        let mut voters = VoterSet::new();
+        let mut state = VotingMachineState::new(2, voters.root());
+        state.window = Some(PollWindow::new(100, 200));
+        let ballot = Ballot {
+            voter: 0,
+            vote: Vote::Choice(1),
+            passphrase: None,
+        };
+        assert!(!state.vote(&ballot, &voters.prove(0), None, None, 99));
+        assert!(!state.vote(&ballot, &voters.prove(0), None, None, 200));
+        assert!(state.vote(&ballot, &voters.prove(0), None, None, 100));
+        voters.insert(0);
+
+        let ballot = Ballot { voter: 1, ..ballot };
+        assert!(state.vote(&ballot, &voters.prove(1), None, None, 199));
+        assert_eq!(state.tally, vec![0, 2]);
+
+        // Freezing still closes the polls early.
+        state.polls_open = false;
+        assert!(!state.is_accepting(150));
+    }
+
+    #[test]
+    fn weighted_ballots_add_their_weight() {
+        let mut weights = WeightTable::new();
+        weights.set(0, 40);
+        weights.set(1, 25);
+        let mut voters = VoterSet::new();
+        let mut state = VotingMachineState::new(2, voters.root());
+        state.weight_root = Some(weights.root());
+
+        let ballot = |voter, vote| Ballot {
+            voter,
+            vote,
+            passphrase: None,
+        };
+        let mut cast = |state: &mut VotingMachineState, voter, vote| {
+            let weight_proof = weights.prove(voter);
+            let counted = state.vote(
+                &ballot(voter, vote),
+                &voters.prove(voter),
+                None,
+                Some(&weight_proof),
+                0,
+            );
+            if counted {
+                voters.insert(voter);
+            }
+            counted
+        };
+        assert!(cast(&mut state, 0, Vote::Choice(1)));
+        assert!(cast(&mut state, 1, Vote::Choice(0)));
+        // Voters left out of the table weigh nothing, and rankings are not taken.
+        assert!(!cast(&mut state, 2, Vote::Choice(0)));
+        assert!(!cast(&mut state, 2, Vote::Ranked(vec![0, 1])));
+        assert_eq!(state.tally, vec![25, 40]);
+    }
+
+    #[test]
+    #[should_panic(expected = "weight proof does not match")]
+    fn forged_weight_is_rejected() {
+        let mut weights = WeightTable::new();
+        weights.set(0, 1);
+        let voters = VoterSet::new();
+        let mut state = VotingMachineState::new(2, voters.root());
+        state.weight_root = Some(weights.root());
+        let mut weight_proof = weights.prove(0);
+        weight_proof.weight = 1_000;
+        let ballot = Ballot {
+            voter: 0,
+            vote: Vote::Choice(0),
+            passphrase: None,
+        };
+        state.vote(&ballot, &voters.prove(0), None, Some(&weight_proof), 0);
+    }
+}
@@ -0,0 +1,248 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+use risc0_zkp::core::sha::{Digest, Sha};
+use risc0_zkvm::sha::sha;
+use serde::{Deserialize, Serialize};
+
+/// Depth of the sparse Merkle trees. Every `u32` voter index has its own leaf.
+pub const VOTER_TREE_DEPTH: usize = 32;
+
+/// Leaf value for the voter set. Voters who have not voted have an all-zero leaf, which is also
+/// what empty subtrees are built up from.
+fn voted_leaf(voted: bool) -> Digest {
+    Digest::new([voted as u32, 0, 0, 0, 0, 0, 0, 0])
+}
+
+fn hash_pair(left: &Digest, right: &Digest) -> Digest {
+    *sha().hash_pair(left, right)
+}
+
+/// Inclusion proof for a single leaf of a [SparseMerkleTree], as the list of sibling nodes from
+/// the leaf up to the root.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct MerkleProof {
+    pub siblings: Vec<Digest>,
+}
+
+impl MerkleProof {
+    /// Compute the root of the tree the proof belongs to, assuming the leaf at `index` holds
+    /// `leaf`.
+    pub fn root(&self, index: u32, leaf: &Digest) -> Digest {
+        assert_eq!(self.siblings.len(), VOTER_TREE_DEPTH);
+        let mut node = *leaf;
+        for (level, sibling) in self.siblings.iter().enumerate() {
+            node = if (index >> level) & 1 == 0 {
+                hash_pair(&node, sibling)
+            } else {
+                hash_pair(sibling, &node)
+            };
+        }
+        node
+    }
+}
+
+/// Sparse Merkle tree with a digest leaf for every `u32` index. Leaves that were never set are all
+/// zero.
+///
+/// Only the nodes that differ from an empty subtree are stored, so the tree stays small no matter
+/// how large the indices get. This is held by the host; the guest only ever sees the root and a
+/// [MerkleProof] for the leaf it is processing.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct SparseMerkleTree {
+    /// Non-empty nodes, keyed by (level, index) with level 0 being the leaves.
+    nodes: BTreeMap<(u32, u32), Digest>,
+    /// Root of an empty subtree at each level, from the leaves up to the root.
+    empty: Vec<Digest>,
+}
+
+impl SparseMerkleTree {
+    pub fn new() -> Self {
+        let mut empty = Vec::with_capacity(VOTER_TREE_DEPTH + 1);
+        empty.push(Digest::default());
+        for level in 0..VOTER_TREE_DEPTH {
+            empty.push(hash_pair(&empty[level], &empty[level]));
+        }
+        SparseMerkleTree {
+            nodes: BTreeMap::new(),
+            empty,
+        }
+    }
+
+    fn node(&self, level: usize, index: u32) -> Digest {
+        match self.nodes.get(&(level as u32, index)) {
+            Some(node) => *node,
+            None => self.empty[level],
+        }
+    }
+
+    pub fn root(&self) -> Digest {
+        self.node(VOTER_TREE_DEPTH, 0)
+    }
+
+    pub fn get(&self, index: u32) -> Option<&Digest> {
+        self.nodes.get(&(0, index))
+    }
+
+    pub fn prove(&self, index: u32) -> MerkleProof {
+        let siblings = (0..VOTER_TREE_DEPTH)
+            .map(|level| self.node(level, (index >> level) ^ 1))
+            .collect();
+        MerkleProof { siblings }
+    }
+
+    /// Set the leaf at `index`, updating the path from the leaf to the root.
+    pub fn insert(&mut self, index: u32, leaf: Digest) {
+        let mut node = leaf;
+        self.nodes.insert((0, index), node);
+        for level in 0..VOTER_TREE_DEPTH {
+            let position = index >> level;
+            let sibling = self.node(level, position ^ 1);
+            node = if position & 1 == 0 {
+                hash_pair(&node, &sibling)
+            } else {
+                hash_pair(&sibling, &node)
+            };
+            // Shifting a u32 right by 32 overflows, so the root index is written out explicitly.
+            let parent = if level + 1 == VOTER_TREE_DEPTH {
+                0
+            } else {
+                index >> (level + 1)
+            };
+            self.nodes.insert((level as u32 + 1, parent), node);
+        }
+    }
+}
+
+impl Default for SparseMerkleTree {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Inclusion proof for a single voter's "has voted" flag in a [VoterSet].
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct VoterProof {
+    pub path: MerkleProof,
+}
+
+impl VoterProof {
+    /// Compute the root of the voter set the proof belongs to, assuming the leaf for `voter` holds
+    /// the given voted flag.
+    pub fn root(&self, voter: u32, voted: bool) -> Digest {
+        self.path.root(voter, &voted_leaf(voted))
+    }
+
+    /// Check the proof against the given root and return whether `voter` has already voted, or
+    /// `None` if the proof does not belong to a voter set with that root.
+    pub fn has_voted(&self, root: &Digest, voter: u32) -> Option<bool> {
+        if &self.root(voter, false) == root {
+            Some(false)
+        } else if &self.root(voter, true) == root {
+            Some(true)
+        } else {
+            None
+        }
+    }
+}
+
+/// Sparse Merkle tree of "has voted" flags, indexed by voter.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct VoterSet {
+    tree: SparseMerkleTree,
+}
+
+impl VoterSet {
+    pub fn new() -> Self {
+        VoterSet {
+            tree: SparseMerkleTree::new(),
+        }
+    }
+
+    pub fn root(&self) -> Digest {
+        self.tree.root()
+    }
+
+    pub fn has_voted(&self, voter: u32) -> bool {
+        self.tree.get(voter).is_some()
+    }
+
+    pub fn prove(&self, voter: u32) -> VoterProof {
+        VoterProof {
+            path: self.tree.prove(voter),
+        }
+    }
+
+    /// Mark `voter` as having voted.
+    pub fn insert(&mut self, voter: u32) {
+        self.tree.insert(voter, voted_leaf(true));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn empty_set_proves_non_membership() {
+        let voters = VoterSet::new();
+        for voter in [0, 1, 31, 32, 40_000, u32::MAX] {
+            assert_eq!(
+                voters.prove(voter).has_voted(&voters.root(), voter),
+                Some(false)
+            );
+        }
+    }
+
+    #[test]
+    fn insert_matches_proof_root() {
+        let mut voters = VoterSet::new();
+        for voter in [0, 1, 33, 40_000, u32::MAX] {
+            let proof = voters.prove(voter);
+            assert_eq!(proof.has_voted(&voters.root(), voter), Some(false));
+            let expected_root = proof.root(voter, true);
+            voters.insert(voter);
+            assert_eq!(voters.root(), expected_root);
+            assert!(voters.has_voted(voter));
+            assert_eq!(
+                voters.prove(voter).has_voted(&voters.root(), voter),
+                Some(true)
+            );
+        }
+    }
+
+    #[test]
+    fn proof_for_other_voter_is_rejected() {
+        let mut voters = VoterSet::new();
+        voters.insert(7);
+        let proof = voters.prove(7);
+        assert_eq!(proof.has_voted(&voters.root(), 6), None);
+    }
+
+    #[test]
+    fn tree_proves_arbitrary_leaves() {
+        let mut tree = SparseMerkleTree::new();
+        let leaf = Digest::new([9; 8]);
+        tree.insert(12_345, leaf);
+        assert_eq!(tree.get(12_345), Some(&leaf));
+        assert_eq!(tree.prove(12_345).root(12_345, &leaf), tree.root());
+        assert_ne!(
+            tree.prove(12_345).root(12_345, &Digest::default()),
+            tree.root()
+        );
+    }
+}
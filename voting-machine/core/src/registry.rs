@@ -0,0 +1,74 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::fmt::{Debug, Formatter};
+
+use risc0_zkp::core::sha::{Digest, Sha};
+use risc0_zkvm::sha::sha;
+use serde::{Deserialize, Serialize};
+
+use crate::merkle::{MerkleProof, SparseMerkleTree};
+
+/// SHA-256 hash of a voter's passphrase. Only the voter and the guest ever see this.
+#[derive(Clone, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Passphrase {
+    pub pass: [u8; 32],
+}
+
+impl Passphrase {
+    /// The public identity for this passphrase, as registered for the voter.
+    pub fn identity(&self) -> Digest {
+        *sha().hash_bytes(&self.pass)
+    }
+}
+
+// Passphrases end up inside ballots, which get logged, so keep them out of debug output.
+impl Debug for Passphrase {
+    fn fmt(&self, f: &mut Formatter) -> core::fmt::Result {
+        f.write_str("Passphrase(..)")
+    }
+}
+
+/// Registered voter identities, held in a sparse Merkle tree indexed by voter.
+///
+/// Elections with a registry commit to its root, and only accept a ballot for a voter if it
+/// carries the passphrase behind that voter's registered identity.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct VoterRegistry {
+    tree: SparseMerkleTree,
+}
+
+impl VoterRegistry {
+    pub fn new() -> Self {
+        VoterRegistry {
+            tree: SparseMerkleTree::new(),
+        }
+    }
+
+    pub fn root(&self) -> Digest {
+        self.tree.root()
+    }
+
+    pub fn register(&mut self, voter: u32, identity: Digest) {
+        self.tree.insert(voter, identity);
+    }
+
+    pub fn identity(&self, voter: u32) -> Option<&Digest> {
+        self.tree.get(voter)
+    }
+
+    pub fn prove(&self, voter: u32) -> MerkleProof {
+        self.tree.prove(voter)
+    }
+}
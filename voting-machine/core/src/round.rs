@@ -0,0 +1,122 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+use risc0_zkp::core::sha::Digest;
+use serde::{Deserialize, Serialize};
+
+use crate::{DecisionRule, PollWindow, VoterSet, VotingMachineState};
+
+/// Public record of a new round being started from a frozen election.
+#[derive(Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct NewRoundCommit {
+    /// Digest of the frozen state the round was started from.
+    pub old_state: Digest,
+    pub new_state: Digest,
+    pub round: u32,
+    pub polls_open: bool,
+    pub voter_root: Digest,
+    pub tally: Vec<u32>,
+    pub registry_root: Option<Digest>,
+    pub weight_root: Option<Digest>,
+    pub window: Option<PollWindow>,
+    pub decision_rule: Option<DecisionRule>,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct NewRoundParams {
+    pub state: VotingMachineState,
+    /// When ballots may be cast in the new round.
+    pub window: Option<PollWindow>,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct NewRoundResult {
+    pub state: VotingMachineState,
+}
+
+impl NewRoundParams {
+    pub fn new(state: VotingMachineState, window: Option<PollWindow>) -> Self {
+        NewRoundParams {
+            state: state,
+            window: window,
+        }
+    }
+
+    /// Start the next round of a frozen election.
+    ///
+    /// The options, the voter registry, the weight table, the decision rule and the ballot salt
+    /// carry over, so the same voters are eligible, with the same weights, as before. Every voter
+    /// may vote again, so the new round starts with an empty voter set and zeroed tallies, and it
+    /// records the digest of the frozen state it follows.
+    pub fn process(&self) -> NewRoundResult {
+        assert!(
+            !self.state.polls_open,
+            "polls must be frozen before starting a new round"
+        );
+        let state = VotingMachineState {
+            polls_open: true,
+            voter_root: VoterSet::new().root(),
+            tally: vec![0; self.state.tally.len()],
+            rankings: Vec::new(),
+            ballot_salt: self.state.ballot_salt,
+            registry_root: self.state.registry_root,
+            weight_root: self.state.weight_root,
+            window: self.window,
+            decision_rule: self.state.decision_rule,
+            round: self.state.round + 1,
+            previous_round: Some(self.state.digest()),
+        };
+        NewRoundResult { state: state }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Ballot, FreezeVotingMachineParams, Vote};
+
+    #[test]
+    fn new_round_resets_tally_and_voters() {
+        let mut voters = VoterSet::new();
+        let mut state = VotingMachineState::new(2, voters.root());
+        let ballot = Ballot {
+            voter: 4,
+            vote: Vote::Choice(1),
+            passphrase: None,
+        };
+        assert!(state.vote(&ballot, &voters.prove(4), None, None, 0));
+        voters.insert(4);
+        let frozen = FreezeVotingMachineParams::new(state).process().state;
+
+        let mut next = NewRoundParams::new(frozen.clone(), None).process().state;
+        assert!(next.polls_open);
+        assert_eq!(next.round, 1);
+        assert_eq!(next.previous_round, Some(frozen.digest()));
+        assert_eq!(next.tally, vec![0, 0]);
+
+        // The voter can vote again in the new round.
+        assert!(next.vote(&ballot, &VoterSet::new().prove(4), None, None, 0));
+        assert_eq!(next.tally, vec![0, 1]);
+    }
+
+    #[test]
+    #[should_panic(expected = "polls must be frozen")]
+    fn open_election_cannot_start_a_new_round() {
+        let state = VotingMachineState::new(2, VoterSet::new().root());
+        NewRoundParams::new(state, None).process();
+    }
+}
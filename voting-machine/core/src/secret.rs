@@ -0,0 +1,160 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::vec::Vec;
+
+use risc0_zkp::core::sha::{Digest, Sha};
+use risc0_zkvm::sha::sha;
+use serde::{Deserialize, Serialize};
+
+use crate::{Ballot, MerkleProof, VoterProof, VotingMachineState, WeightProof};
+
+/// Public commit for a ballot in a secret-ballot election. Neither the voter nor their choice is
+/// revealed; only the nullifier and the state transition are.
+#[derive(Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct SubmitSecretBallotCommit {
+    pub old_state: Digest,
+    pub new_state: Digest,
+    /// Tag the voter can recompute from their nonce to find the receipt for their ballot.
+    pub nullifier: Digest,
+    /// Time the host says the ballot was cast at.
+    pub time: u64,
+    pub vote_counted: bool,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct SubmitSecretBallotParams {
+    pub state: VotingMachineState,
+    pub ballot: Ballot,
+    pub voter_proof: VoterProof,
+    pub registry_proof: Option<MerkleProof>,
+    pub weight_proof: Option<WeightProof>,
+    /// Random value chosen by the voter and kept private.
+    pub nonce: Digest,
+    /// Time the ballot was cast at, in seconds since the Unix epoch.
+    pub time: u64,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct SubmitSecretBallotResult {
+    pub state: VotingMachineState,
+    pub vote_counted: bool,
+}
+
+impl SubmitSecretBallotParams {
+    pub fn new(
+        state: VotingMachineState,
+        ballot: Ballot,
+        voter_proof: VoterProof,
+        registry_proof: Option<MerkleProof>,
+        weight_proof: Option<WeightProof>,
+        nonce: Digest,
+        time: u64,
+    ) -> Self {
+        SubmitSecretBallotParams {
+            state: state,
+            ballot: ballot,
+            voter_proof: voter_proof,
+            registry_proof: registry_proof,
+            weight_proof: weight_proof,
+            nonce: nonce,
+            time: time,
+        }
+    }
+
+    pub fn nullifier(&self) -> Digest {
+        nullifier(self.ballot.voter, &self.nonce)
+    }
+
+    pub fn process(&self) -> SubmitSecretBallotResult {
+        assert!(
+            self.state.is_secret_ballot(),
+            "public ballots must be submitted with SubmitBallotParams"
+        );
+        let mut state = self.state.clone();
+        let vote_counted = state.vote(
+            &self.ballot,
+            &self.voter_proof,
+            self.registry_proof.as_ref(),
+            self.weight_proof.as_ref(),
+            self.time,
+        );
+        SubmitSecretBallotResult {
+            state: state,
+            vote_counted: vote_counted,
+        }
+    }
+}
+
+/// Compute the nullifier committed for a secret ballot from the voter and their private nonce.
+pub fn nullifier(voter: u32, nonce: &Digest) -> Digest {
+    let mut data = Vec::with_capacity(36);
+    data.extend_from_slice(&voter.to_le_bytes());
+    data.extend_from_slice(nonce.as_bytes());
+    *sha().hash_bytes(&data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{SubmitBallotParams, Vote, VoterSet};
+
+    fn secret_state(voters: &VoterSet) -> VotingMachineState {
+        VotingMachineState::new_secret(2, voters.root(), Digest::new([7; 8]))
+    }
+
+    #[test]
+    fn secret_ballot_is_counted() {
+        let voters = VoterSet::new();
+        let ballot = Ballot {
+            voter: 3,
+            vote: Vote::Choice(1),
+            passphrase: None,
+        };
+        let params = SubmitSecretBallotParams::new(
+            secret_state(&voters),
+            ballot,
+            voters.prove(3),
+            None,
+            None,
+            Digest::new([1; 8]),
+            0,
+        );
+        let result = params.process();
+        assert!(result.vote_counted);
+        assert_eq!(result.state.tally, vec![0, 1]);
+        assert_eq!(params.nullifier(), nullifier(3, &Digest::new([1; 8])));
+        assert_ne!(params.nullifier(), nullifier(4, &Digest::new([1; 8])));
+    }
+
+    #[test]
+    #[should_panic(expected = "secret ballots must be submitted")]
+    fn public_submit_is_rejected() {
+        let voters = VoterSet::new();
+        let ballot = Ballot {
+            voter: 3,
+            vote: Vote::Choice(1),
+            passphrase: None,
+        };
+        SubmitBallotParams::new(
+            secret_state(&voters),
+            ballot,
+            voters.prove(3),
+            None,
+            None,
+            0,
+        )
+        .process();
+    }
+}
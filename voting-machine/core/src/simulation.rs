@@ -0,0 +1,320 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Randomized and adversarial tests of the state transitions run by the guests.
+//!
+//! Each generated election is a sequence of single ballots, batches and freezes, mixing in double
+//! votes, ballots after the polls close, invalid votes and voters at the edges of the index range.
+//! The machine is checked after every step against a plain model of the rules, built on a set of
+//! voters rather than a Merkle tree. Elections are seeded by their case number, so a failure names
+//! the seed that reproduces it.
+
+use std::collections::BTreeSet;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::{
+    Ballot, FreezeVotingMachineParams, SubmitBallotParams, SubmitBatchParams, Vote, VoterSet,
+    VotingMachineState,
+};
+
+const CASES: u64 = 200;
+const MAX_STEPS: usize = 40;
+const OPTIONS: u32 = 3;
+
+/// Voters drawn from outside the small pool most ballots use.
+const EDGE_VOTERS: [u32; 4] = [u32::MAX, u32::MAX - 1, 1 << 31, 1 << 16];
+
+enum Step {
+    Submit(Ballot),
+    SubmitBatch(Vec<Ballot>),
+    Freeze,
+}
+
+fn random_voter(rng: &mut StdRng) -> u32 {
+    // A small pool makes double votes common.
+    if rng.gen_bool(0.1) {
+        EDGE_VOTERS[rng.gen_range(0..EDGE_VOTERS.len())]
+    } else {
+        rng.gen_range(0..8)
+    }
+}
+
+fn random_vote(rng: &mut StdRng) -> Vote {
+    // Options run one past the end so that some votes are out of range.
+    if rng.gen_bool(0.5) {
+        Vote::Choice(rng.gen_range(0..=OPTIONS))
+    } else {
+        let len = rng.gen_range(0..=OPTIONS as usize + 1);
+        Vote::Ranked((0..len).map(|_| rng.gen_range(0..=OPTIONS)).collect())
+    }
+}
+
+fn random_ballot(rng: &mut StdRng) -> Ballot {
+    Ballot {
+        voter: random_voter(rng),
+        vote: random_vote(rng),
+        passphrase: None,
+    }
+}
+
+fn random_step(rng: &mut StdRng) -> Step {
+    match rng.gen_range(0..20) {
+        0 => Step::Freeze,
+        1..=4 => {
+            let len = rng.gen_range(0..6);
+            Step::SubmitBatch((0..len).map(|_| random_ballot(rng)).collect())
+        }
+        _ => Step::Submit(random_ballot(rng)),
+    }
+}
+
+/// The rules of the election, written out directly.
+struct Model {
+    polls_open: bool,
+    voted: BTreeSet<u32>,
+    tally: Vec<u32>,
+    rankings: Vec<Vec<u32>>,
+}
+
+impl Model {
+    fn new() -> Self {
+        Model {
+            polls_open: true,
+            voted: BTreeSet::new(),
+            tally: vec![0; OPTIONS as usize],
+            rankings: Vec::new(),
+        }
+    }
+
+    fn vote(&mut self, ballot: &Ballot) -> bool {
+        let options = match &ballot.vote {
+            Vote::Choice(option) => vec![*option],
+            Vote::Ranked(ranking) => ranking.clone(),
+        };
+        let distinct = options.iter().collect::<BTreeSet<_>>().len() == options.len();
+        let valid = !options.is_empty() && distinct && options.iter().all(|o| *o < OPTIONS);
+        if !self.polls_open || !valid || !self.voted.insert(ballot.voter) {
+            return false;
+        }
+        self.tally[options[0] as usize] += 1;
+        if let Vote::Ranked(ranking) = &ballot.vote {
+            self.rankings.push(ranking.clone());
+        }
+        true
+    }
+}
+
+/// What a polling station keeps: the state, and the voter set behind its root.
+struct Station {
+    state: VotingMachineState,
+    voters: VoterSet,
+}
+
+impl Station {
+    fn new() -> Self {
+        let voters = VoterSet::new();
+        Station {
+            state: VotingMachineState::new(OPTIONS, voters.root()),
+            voters,
+        }
+    }
+
+    fn submit(&mut self, ballot: &Ballot) -> bool {
+        let params = SubmitBallotParams::new(
+            self.state.clone(),
+            ballot.clone(),
+            self.voters.prove(ballot.voter),
+            None,
+            None,
+            0,
+        );
+        let result = params.process();
+        if result.vote_counted {
+            self.voters.insert(ballot.voter);
+        }
+        self.state = result.state;
+        result.vote_counted
+    }
+
+    fn submit_batch(&mut self, ballots: &[Ballot]) -> Vec<bool> {
+        let mut state = self.state.clone();
+        let mut voters = self.voters.clone();
+        let mut voter_proofs = Vec::new();
+        for ballot in ballots {
+            let voter_proof = voters.prove(ballot.voter);
+            if state.vote(ballot, &voter_proof, None, None, 0) {
+                voters.insert(ballot.voter);
+            }
+            voter_proofs.push(voter_proof);
+        }
+        let params = SubmitBatchParams::new(
+            self.state.clone(),
+            ballots.to_vec(),
+            voter_proofs,
+            vec![None; ballots.len()],
+            vec![None; ballots.len()],
+            0,
+        );
+        let result = params.process();
+        self.state = result.state;
+        self.voters = voters;
+        result.vote_counted
+    }
+
+    fn freeze(&mut self) {
+        self.state = FreezeVotingMachineParams::new(self.state.clone())
+            .process()
+            .state;
+    }
+}
+
+fn check_invariants(seed: u64, station: &Station, model: &Model) {
+    let state = &station.state;
+    // Every voter marked in the voter set was counted exactly once.
+    let counted: u32 = state.tally.iter().sum();
+    assert_eq!(counted as usize, model.voted.len(), "seed {}", seed);
+    assert_eq!(state.polls_open, model.polls_open, "seed {}", seed);
+    assert_eq!(state.tally, model.tally, "seed {}", seed);
+    assert_eq!(state.rankings, model.rankings, "seed {}", seed);
+
+    let mut voters = VoterSet::new();
+    for voter in &model.voted {
+        voters.insert(*voter);
+    }
+    assert_eq!(state.voter_root, voters.root(), "seed {}", seed);
+    assert_eq!(state.voter_root, station.voters.root(), "seed {}", seed);
+}
+
+#[test]
+fn random_elections_follow_the_rules() {
+    for seed in 0..CASES {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut station = Station::new();
+        let mut model = Model::new();
+        for _ in 0..rng.gen_range(0..MAX_STEPS) {
+            let before = station.state.digest();
+            let changed = match random_step(&mut rng) {
+                Step::Submit(ballot) => {
+                    let counted = station.submit(&ballot);
+                    assert_eq!(counted, model.vote(&ballot), "seed {}", seed);
+                    counted
+                }
+                Step::SubmitBatch(ballots) => {
+                    let counted = station.submit_batch(&ballots);
+                    let expected: Vec<bool> = ballots.iter().map(|b| model.vote(b)).collect();
+                    assert_eq!(counted, expected, "seed {}", seed);
+                    counted.contains(&true)
+                }
+                Step::Freeze => {
+                    station.freeze();
+                    let changed = model.polls_open;
+                    model.polls_open = false;
+                    changed
+                }
+            };
+            // A step that counts nothing must leave the state exactly as it was.
+            assert_eq!(station.state.digest() != before, changed, "seed {}", seed);
+            check_invariants(seed, &station, &model);
+        }
+        // Whatever happened, the frozen election can be tallied.
+        station.freeze();
+        crate::tally::instant_runoff(&station.state.tally, &station.state.rankings);
+    }
+}
+
+fn ballot(voter: u32, option: u32) -> Ballot {
+    Ballot {
+        voter,
+        vote: Vote::Choice(option),
+        passphrase: None,
+    }
+}
+
+#[test]
+fn stale_proof_for_the_same_voter_still_shows_the_vote() {
+    let mut station = Station::new();
+    let stale_proof = station.voters.prove(3);
+    assert!(station.submit(&ballot(3, 0)));
+    // Marking a voter only changes their own leaf, so a proof taken before they voted still leads
+    // to the new root, and shows that they have voted.
+    let result = SubmitBallotParams::new(
+        station.state.clone(),
+        ballot(3, 1),
+        stale_proof,
+        None,
+        None,
+        0,
+    )
+    .process();
+    assert!(!result.vote_counted);
+    assert_eq!(result.state, station.state);
+}
+
+#[test]
+#[should_panic(expected = "voter proof does not match")]
+fn proof_from_before_other_ballots_is_rejected() {
+    let mut station = Station::new();
+    let stale_proof = station.voters.prove(2);
+    assert!(station.submit(&ballot(1, 0)));
+    SubmitBallotParams::new(station.state, ballot(2, 0), stale_proof, None, None, 0).process();
+}
+
+#[test]
+#[should_panic(expected = "voter proof does not match")]
+fn proof_for_another_voter_set_is_rejected() {
+    let mut station = Station::new();
+    assert!(station.submit(&ballot(1, 0)));
+    let mut other = VoterSet::new();
+    other.insert(2);
+    SubmitBallotParams::new(station.state, ballot(2, 0), other.prove(2), None, None, 0).process();
+}
+
+#[test]
+fn batch_cannot_hide_a_double_vote() {
+    let station = Station::new();
+    // Both proofs are made against the starting voter set, which does not yet show the first
+    // ballot from the same voter.
+    let ballots = vec![ballot(5, 0), ballot(5, 1)];
+    let voter_proofs = vec![station.voters.prove(5), station.voters.prove(5)];
+    let result = SubmitBatchParams::new(
+        station.state,
+        ballots,
+        voter_proofs,
+        vec![None, None],
+        vec![None, None],
+        0,
+    )
+    .process();
+    assert_eq!(result.vote_counted, vec![true, false]);
+    assert_eq!(result.state.tally, vec![1, 0, 0]);
+}
+
+#[test]
+#[should_panic(expected = "voter proof does not match")]
+fn batch_proofs_must_reflect_earlier_ballots() {
+    let station = Station::new();
+    let ballots = vec![ballot(0, 0), ballot(1, 1)];
+    let voter_proofs = vec![station.voters.prove(0), station.voters.prove(1)];
+    SubmitBatchParams::new(
+        station.state,
+        ballots,
+        voter_proofs,
+        vec![None, None],
+        vec![None, None],
+        0,
+    )
+    .process();
+}
@@ -0,0 +1,166 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+use risc0_zkp::core::sha::Digest;
+use serde::{Deserialize, Serialize};
+
+use crate::VotingMachineState;
+
+/// Public result of tallying a frozen voting machine.
+#[derive(Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct TallyCommit {
+    /// Digest of the frozen state that was tallied.
+    pub state: Digest,
+    /// Number of first-choice votes for each option.
+    pub tally: Vec<u32>,
+    /// Per-option counts for each instant-runoff round. Eliminated options count zero.
+    pub rounds: Vec<Vec<u32>>,
+    pub winner: Option<u32>,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct TallyParams {
+    pub state: VotingMachineState,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct TallyResult {
+    pub rounds: Vec<Vec<u32>>,
+    pub winner: Option<u32>,
+}
+
+impl TallyParams {
+    pub fn new(state: VotingMachineState) -> Self {
+        TallyParams { state: state }
+    }
+
+    pub fn process(&self) -> TallyResult {
+        assert!(
+            !self.state.polls_open,
+            "polls must be frozen before tallying"
+        );
+        instant_runoff(&self.state.tally, &self.state.rankings)
+    }
+}
+
+/// Run an instant-runoff count.
+///
+/// Each round counts every ballot toward its most preferred option that is still in the running.
+/// An option with more than half of the counted ballots wins; otherwise the option with the fewest
+/// votes is eliminated, with ties going against the higher-numbered option. Single-choice votes
+/// are the part of `tally` not accounted for by `rankings`, and count as a ranking of one option.
+pub fn instant_runoff(tally: &[u32], rankings: &[Vec<u32>]) -> TallyResult {
+    let options = tally.len();
+    let mut single_choice = tally.to_vec();
+    for ranking in rankings {
+        let first = &mut single_choice[ranking[0] as usize];
+        *first = first
+            .checked_sub(1)
+            .expect("rankings do not match the first-choice tally");
+    }
+
+    let mut eliminated = vec![false; options];
+    let mut rounds = Vec::new();
+    loop {
+        let mut counts = vec![0u32; options];
+        for (option, votes) in single_choice.iter().enumerate() {
+            if !eliminated[option] {
+                counts[option] += votes;
+            }
+        }
+        for ranking in rankings {
+            if let Some(option) = ranking.iter().find(|option| !eliminated[**option as usize]) {
+                counts[*option as usize] += 1;
+            }
+        }
+        let total: u32 = counts.iter().sum();
+        let remaining: Vec<usize> = (0..options).filter(|option| !eliminated[*option]).collect();
+        rounds.push(counts.clone());
+
+        if total == 0 {
+            return TallyResult {
+                rounds,
+                winner: None,
+            };
+        }
+        // Iterating in reverse makes ties go to the lower-numbered option.
+        let leader = *remaining
+            .iter()
+            .rev()
+            .max_by_key(|option| counts[**option])
+            .unwrap();
+        if 2 * counts[leader] > total {
+            return TallyResult {
+                rounds,
+                winner: Some(leader as u32),
+            };
+        }
+        let loser = *remaining
+            .iter()
+            .rev()
+            .min_by_key(|option| counts[**option])
+            .unwrap();
+        eliminated[loser] = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn majority_wins_in_first_round() {
+        let result = instant_runoff(&[3, 1, 0], &[]);
+        assert_eq!(result.rounds, vec![vec![3, 1, 0]]);
+        assert_eq!(result.winner, Some(0));
+    }
+
+    #[test]
+    fn preferences_transfer_on_elimination() {
+        // Option 2 is eliminated first and its ballots transfer to option 1.
+        let rankings = vec![
+            vec![0],
+            vec![0],
+            vec![1],
+            vec![1, 0],
+            vec![2, 1],
+            vec![2, 1],
+        ];
+        let result = instant_runoff(&[2, 2, 2], &rankings);
+        assert_eq!(result.rounds, vec![vec![2, 2, 2], vec![2, 4, 0]]);
+        assert_eq!(result.winner, Some(1));
+    }
+
+    #[test]
+    fn single_choice_votes_exhaust() {
+        // Options 1 and 2 each have one single-choice vote. Once eliminated, those votes are not
+        // counted again, while the ranked ballot for option 2 moves on to option 0.
+        let rankings = vec![vec![0], vec![0], vec![2, 0]];
+        let result = instant_runoff(&[2, 1, 2], &rankings);
+        assert_eq!(
+            result.rounds,
+            vec![vec![2, 1, 2], vec![2, 0, 2], vec![3, 0, 0]]
+        );
+        assert_eq!(result.winner, Some(0));
+    }
+
+    #[test]
+    fn no_votes_has_no_winner() {
+        let result = instant_runoff(&[0, 0], &[]);
+        assert_eq!(result.winner, None);
+    }
+}
@@ -0,0 +1,96 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use risc0_zkp::core::sha::Digest;
+use serde::{Deserialize, Serialize};
+
+use crate::merkle::{MerkleProof, SparseMerkleTree};
+
+/// Leaf value for a voter's weight. Voters left out of the table weigh nothing, and have the
+/// all-zero leaf that empty subtrees are built up from.
+fn weight_leaf(weight: u32) -> Digest {
+    Digest::new([weight, 0, 0, 0, 0, 0, 0, 0])
+}
+
+/// Voting weight of each voter, held in a sparse Merkle tree indexed by voter.
+///
+/// Weighted elections commit to its root, and add each counted ballot's weight to the tally
+/// instead of one.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct WeightTable {
+    tree: SparseMerkleTree,
+}
+
+impl WeightTable {
+    pub fn new() -> Self {
+        WeightTable {
+            tree: SparseMerkleTree::new(),
+        }
+    }
+
+    pub fn root(&self) -> Digest {
+        self.tree.root()
+    }
+
+    pub fn set(&mut self, voter: u32, weight: u32) {
+        self.tree.insert(voter, weight_leaf(weight));
+    }
+
+    /// The weight of `voter`, which is zero for voters left out of the table.
+    pub fn weight(&self, voter: u32) -> u32 {
+        self.tree.get(voter).map_or(0, |leaf| leaf.get()[0])
+    }
+
+    pub fn prove(&self, voter: u32) -> WeightProof {
+        WeightProof {
+            weight: self.weight(voter),
+            path: self.tree.prove(voter),
+        }
+    }
+}
+
+/// A voter's weight, along with its inclusion proof in a [WeightTable].
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct WeightProof {
+    pub weight: u32,
+    pub path: MerkleProof,
+}
+
+impl WeightProof {
+    /// Compute the root of the weight table the proof belongs to.
+    pub fn root(&self, voter: u32) -> Digest {
+        self.path.root(voter, &weight_leaf(self.weight))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn weights_prove_against_the_root() {
+        let mut weights = WeightTable::new();
+        weights.set(2, 100);
+        weights.set(9, 5);
+        assert_eq!(weights.weight(2), 100);
+        assert_eq!(weights.weight(3), 0);
+        for voter in [2, 3, 9] {
+            assert_eq!(weights.prove(voter).root(voter), weights.root());
+        }
+
+        let mut forged = weights.prove(9);
+        forged.weight = 500;
+        assert_ne!(forged.root(9), weights.root());
+    }
+}
@@ -0,0 +1,553 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fmt::{Display, Formatter};
+
+use risc0_zkp::core::sha::Digest;
+use serde::de::Error as _;
+use serde::ser::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use voting_machine_core::{Outcome, Vote};
+
+use crate::envelope::{Envelope, Method};
+use crate::{
+    FreezeStationMessage, InitMessage, NewRoundMessage, SubmitBallotMessage, SubmitBatchMessage,
+    SubmitSecretBallotMessage, VotingMachineError,
+};
+
+/// Any message published by a polling station over the course of an election.
+pub enum ElectionMessage {
+    Init(InitMessage),
+    Submit(SubmitBallotMessage),
+    SubmitBatch(SubmitBatchMessage),
+    SubmitSecret(SubmitSecretBallotMessage),
+    Freeze(FreezeStationMessage),
+    NewRound(NewRoundMessage),
+}
+
+impl ElectionMessage {
+    pub fn kind(&self) -> MessageKind {
+        match self {
+            ElectionMessage::Init(_) => MessageKind::Init,
+            ElectionMessage::Submit(_) => MessageKind::Submit,
+            ElectionMessage::SubmitBatch(_) => MessageKind::SubmitBatch,
+            ElectionMessage::SubmitSecret(_) => MessageKind::SubmitSecret,
+            ElectionMessage::Freeze(_) => MessageKind::Freeze,
+            ElectionMessage::NewRound(_) => MessageKind::NewRound,
+        }
+    }
+
+    pub fn to_envelope(&self) -> Result<Envelope, VotingMachineError> {
+        match self {
+            ElectionMessage::Init(msg) => msg.to_envelope(),
+            ElectionMessage::Submit(msg) => msg.to_envelope(),
+            ElectionMessage::SubmitBatch(msg) => msg.to_envelope(),
+            ElectionMessage::SubmitSecret(msg) => msg.to_envelope(),
+            ElectionMessage::Freeze(msg) => msg.to_envelope(),
+            ElectionMessage::NewRound(msg) => msg.to_envelope(),
+        }
+    }
+
+    /// Take the message out of an envelope from any of the methods that make up an election.
+    pub fn from_envelope(envelope: &Envelope) -> Result<Self, VotingMachineError> {
+        Ok(match envelope.kind {
+            Method::Init => InitMessage::from_envelope(envelope)?.into(),
+            Method::Submit => SubmitBallotMessage::from_envelope(envelope)?.into(),
+            Method::SubmitBatch => SubmitBatchMessage::from_envelope(envelope)?.into(),
+            Method::SubmitSecret => SubmitSecretBallotMessage::from_envelope(envelope)?.into(),
+            Method::Freeze => FreezeStationMessage::from_envelope(envelope)?.into(),
+            Method::NewRound => NewRoundMessage::from_envelope(envelope)?.into(),
+            Method::Tally => {
+                return Err(VotingMachineError::decode(
+                    "tally receipts are not election messages",
+                ))
+            }
+        })
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, VotingMachineError> {
+        self.to_envelope()?.to_bytes()
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, VotingMachineError> {
+        Self::from_envelope(&Envelope::from_bytes(bytes)?)
+    }
+}
+
+impl Serialize for ElectionMessage {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.to_envelope()
+            .map_err(S::Error::custom)?
+            .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for ElectionMessage {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let envelope = Envelope::deserialize(deserializer)?;
+        Self::from_envelope(&envelope).map_err(D::Error::custom)
+    }
+}
+
+impl From<InitMessage> for ElectionMessage {
+    fn from(msg: InitMessage) -> Self {
+        ElectionMessage::Init(msg)
+    }
+}
+
+impl From<SubmitBallotMessage> for ElectionMessage {
+    fn from(msg: SubmitBallotMessage) -> Self {
+        ElectionMessage::Submit(msg)
+    }
+}
+
+impl From<SubmitBatchMessage> for ElectionMessage {
+    fn from(msg: SubmitBatchMessage) -> Self {
+        ElectionMessage::SubmitBatch(msg)
+    }
+}
+
+impl From<SubmitSecretBallotMessage> for ElectionMessage {
+    fn from(msg: SubmitSecretBallotMessage) -> Self {
+        ElectionMessage::SubmitSecret(msg)
+    }
+}
+
+impl From<FreezeStationMessage> for ElectionMessage {
+    fn from(msg: FreezeStationMessage) -> Self {
+        ElectionMessage::Freeze(msg)
+    }
+}
+
+impl From<NewRoundMessage> for ElectionMessage {
+    fn from(msg: NewRoundMessage) -> Self {
+        ElectionMessage::NewRound(msg)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum MessageKind {
+    Init,
+    Submit,
+    SubmitBatch,
+    SubmitSecret,
+    Freeze,
+    NewRound,
+}
+
+impl MessageKind {
+    /// The method that produces messages of this kind.
+    pub fn method(&self) -> Method {
+        match self {
+            MessageKind::Init => Method::Init,
+            MessageKind::Submit => Method::Submit,
+            MessageKind::SubmitBatch => Method::SubmitBatch,
+            MessageKind::SubmitSecret => Method::SubmitSecret,
+            MessageKind::Freeze => Method::Freeze,
+            MessageKind::NewRound => Method::NewRound,
+        }
+    }
+}
+
+/// What the audited messages reveal about a ballot.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum AuditedVote {
+    Public {
+        voter: u32,
+        vote: Vote,
+        /// Weight of the voter, which is always one in an unweighted election.
+        weight: u32,
+    },
+    /// A secret ballot, known only by its nullifier.
+    Secret { nullifier: Digest },
+}
+
+/// A ballot found in the audited messages.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AuditedBallot {
+    /// Position of the message that carried the ballot.
+    pub index: usize,
+    /// Round the ballot was cast in.
+    pub round: u32,
+    pub vote: AuditedVote,
+    pub vote_counted: bool,
+}
+
+/// Summary of an election whose messages formed an unbroken chain from init to freeze.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AuditReport {
+    pub initial_state: Digest,
+    pub final_state: Digest,
+    pub voter_root: Digest,
+    /// Number of the last round.
+    pub round: u32,
+    /// Final per-option tally of the last round, as revealed by its freeze.
+    pub tally: Vec<u32>,
+    /// Tally revealed by the freeze of each round, in order.
+    pub round_tallies: Vec<Vec<u32>>,
+    /// Outcome of the motion proven by the last freeze, for elections with a decision rule.
+    pub outcome: Option<Outcome>,
+    pub ballots: Vec<AuditedBallot>,
+}
+
+impl AuditReport {
+    pub fn ballots_counted(&self) -> usize {
+        self.ballots
+            .iter()
+            .filter(|ballot| ballot.vote_counted)
+            .count()
+    }
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum AuditError {
+    /// There were no messages to audit.
+    Empty,
+    /// The first message was not an init message.
+    MissingInit { kind: MessageKind },
+    /// An init message appeared after the start of the election.
+    UnexpectedInit { index: usize },
+    /// The last message was not a freeze message.
+    MissingFreeze { kind: MessageKind },
+    /// The receipt for a message did not verify against its method ID.
+    InvalidReceipt {
+        index: usize,
+        kind: MessageKind,
+        reason: String,
+    },
+    /// The old state of a message did not match the new state of the message before it.
+    BrokenChain {
+        index: usize,
+        kind: MessageKind,
+        expected: Digest,
+        found: Digest,
+    },
+}
+
+impl Display for AuditError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            AuditError::Empty => write!(f, "no messages to audit"),
+            AuditError::MissingInit { kind } => {
+                write!(
+                    f,
+                    "election starts with a {:?} message instead of Init",
+                    kind
+                )
+            }
+            AuditError::UnexpectedInit { index } => {
+                write!(f, "message {} re-initializes the election", index)
+            }
+            AuditError::MissingFreeze { kind } => {
+                write!(
+                    f,
+                    "election ends with a {:?} message instead of Freeze",
+                    kind
+                )
+            }
+            AuditError::InvalidReceipt {
+                index,
+                kind,
+                reason,
+            } => write!(
+                f,
+                "receipt for {:?} message {} is invalid: {}",
+                kind, index, reason
+            ),
+            AuditError::BrokenChain {
+                index,
+                kind,
+                expected,
+                found,
+            } => write!(
+                f,
+                "{:?} message {} starts from state {:?}, but the previous message ended at {:?}",
+                kind, index, found, expected
+            ),
+        }
+    }
+}
+
+impl std::error::Error for AuditError {}
+
+fn invalid_receipt<E: Display>(index: usize, kind: MessageKind) -> impl FnOnce(E) -> AuditError {
+    move |err| AuditError::InvalidReceipt {
+        index,
+        kind,
+        reason: err.to_string(),
+    }
+}
+
+/// Checks that an ordered sequence of election messages tells one consistent story.
+///
+/// Every receipt is verified against the method it claims to come from, and each message must
+/// start from the state the previous message ended at. The sequence must begin with an init and
+/// end with a freeze. A frozen election may go on to further rounds, each of which is started by a
+/// new round message and ended by another freeze.
+#[derive(Default)]
+pub struct ElectionAuditor {
+    messages: Vec<ElectionMessage>,
+}
+
+impl ElectionAuditor {
+    pub fn new() -> Self {
+        ElectionAuditor::default()
+    }
+
+    pub fn push(&mut self, msg: impl Into<ElectionMessage>) {
+        self.messages.push(msg.into());
+    }
+
+    pub fn audit(&self) -> Result<AuditReport, AuditError> {
+        let report = self.audit_partial()?;
+        let last = self.messages.last().unwrap();
+        if last.kind() != MessageKind::Freeze {
+            return Err(AuditError::MissingFreeze { kind: last.kind() });
+        }
+        Ok(report)
+    }
+
+    /// Audit an election that may still be running. This makes all the checks of
+    /// [ElectionAuditor::audit] except that the messages do not have to end with a freeze.
+    pub fn audit_partial(&self) -> Result<AuditReport, AuditError> {
+        let first = self.messages.first().ok_or(AuditError::Empty)?;
+        let init = match first {
+            ElectionMessage::Init(msg) => msg
+                .verify_and_get_commit()
+                .map_err(invalid_receipt(0, MessageKind::Init))?,
+            other => return Err(AuditError::MissingInit { kind: other.kind() }),
+        };
+
+        let mut report = AuditReport {
+            initial_state: init.state,
+            final_state: init.state,
+            voter_root: init.voter_root,
+            round: 0,
+            tally: init.tally,
+            round_tallies: Vec::new(),
+            outcome: None,
+            ballots: Vec::new(),
+        };
+        for (index, msg) in self.messages.iter().enumerate().skip(1) {
+            let kind = msg.kind();
+            let invalid_receipt = invalid_receipt(index, kind);
+            let (old_state, new_state, voter_root) = match msg {
+                ElectionMessage::Init(_) => return Err(AuditError::UnexpectedInit { index }),
+                ElectionMessage::Submit(msg) => {
+                    let commit = msg.verify_and_get_commit().map_err(invalid_receipt)?;
+                    report.ballots.push(AuditedBallot {
+                        index,
+                        round: report.round,
+                        vote: AuditedVote::Public {
+                            voter: commit.voter,
+                            vote: commit.vote,
+                            weight: commit.weight,
+                        },
+                        vote_counted: commit.vote_counted,
+                    });
+                    (commit.old_state, commit.new_state, commit.voter_root)
+                }
+                ElectionMessage::SubmitBatch(msg) => {
+                    let commit = msg.verify_and_get_commit().map_err(invalid_receipt)?;
+                    let ballots = commit.voters.into_iter().zip(commit.votes);
+                    let ballots = ballots.zip(commit.weights).zip(commit.vote_counted);
+                    report.ballots.extend(ballots.map(
+                        |(((voter, vote), weight), vote_counted)| AuditedBallot {
+                            index,
+                            round: report.round,
+                            vote: AuditedVote::Public {
+                                voter,
+                                vote,
+                                weight,
+                            },
+                            vote_counted,
+                        },
+                    ));
+                    (commit.old_state, commit.new_state, commit.voter_root)
+                }
+                ElectionMessage::SubmitSecret(msg) => {
+                    let commit = msg.verify_and_get_commit().map_err(invalid_receipt)?;
+                    report.ballots.push(AuditedBallot {
+                        index,
+                        round: report.round,
+                        vote: AuditedVote::Secret {
+                            nullifier: commit.nullifier,
+                        },
+                        vote_counted: commit.vote_counted,
+                    });
+                    // Secret ballots do not reveal the voter set, so carry the last known root.
+                    (commit.old_state, commit.new_state, report.voter_root)
+                }
+                ElectionMessage::Freeze(msg) => {
+                    let commit = msg.verify_and_get_commit().map_err(invalid_receipt)?;
+                    report.tally = commit.tally.clone();
+                    report.round_tallies.push(commit.tally);
+                    report.outcome = commit.outcome;
+                    (commit.old_state, commit.new_state, commit.voter_root)
+                }
+                ElectionMessage::NewRound(msg) => {
+                    let commit = msg.verify_and_get_commit().map_err(invalid_receipt)?;
+                    report.round = commit.round;
+                    report.tally = commit.tally;
+                    report.outcome = None;
+                    (commit.old_state, commit.new_state, commit.voter_root)
+                }
+            };
+            if old_state != report.final_state {
+                return Err(AuditError::BrokenChain {
+                    index,
+                    kind,
+                    expected: report.final_state,
+                    found: old_state,
+                });
+            }
+            report.final_state = new_state;
+            report.voter_root = voter_root;
+        }
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use risc0_zkvm::serde::to_vec;
+    use risc0_zkvm::Receipt;
+    use serde::Serialize;
+    use voting_machine_core::{Ballot, VoterSet, VotingMachineState};
+
+    use super::*;
+    use crate::PollingStation;
+
+    fn ballot(voter: u32, option: u32) -> Ballot {
+        Ballot {
+            voter,
+            vote: Vote::Choice(option),
+            passphrase: None,
+        }
+    }
+
+    /// Run a short election starting with the given ballot, returning its messages in the order
+    /// they were published.
+    fn election(first: Ballot) -> Vec<ElectionMessage> {
+        let voters = VoterSet::new();
+        let state = VotingMachineState::new(2, voters.root());
+        let mut polling_station = PollingStation::new(state, voters).unwrap();
+        vec![
+            polling_station.init().unwrap().into(),
+            polling_station.submit(&first).unwrap().into(),
+            polling_station.submit(&ballot(1, 0)).unwrap().into(),
+            polling_station.freeze().unwrap().into(),
+        ]
+    }
+
+    fn audit(messages: Vec<ElectionMessage>) -> Result<AuditReport, AuditError> {
+        let mut auditor = ElectionAuditor::new();
+        for msg in messages {
+            auditor.push(msg);
+        }
+        auditor.audit()
+    }
+
+    /// Rewrite the journal of a receipt, keeping its seal.
+    fn tamper<T: Serialize>(receipt: &Receipt, commit: &T) -> Receipt {
+        Receipt::new(&to_vec(commit).unwrap(), &receipt.seal)
+    }
+
+    fn assert_invalid_receipt(result: Result<AuditReport, AuditError>, expected: usize) {
+        match result {
+            Err(AuditError::InvalidReceipt { index, .. }) => assert_eq!(index, expected),
+            other => panic!("expected an invalid receipt, got {:?}", other),
+        }
+    }
+
+    fn assert_broken_chain(result: Result<AuditReport, AuditError>, expected: usize) {
+        match result {
+            Err(AuditError::BrokenChain { index, .. }) => assert_eq!(index, expected),
+            other => panic!("expected a broken chain, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn untampered_election_passes() {
+        let report = audit(election(ballot(0, 1))).unwrap();
+        assert_eq!(report.tally, vec![1, 1]);
+        assert_eq!(report.ballots_counted(), 2);
+    }
+
+    #[test]
+    fn changed_vote_fails_audit() {
+        let mut messages = election(ballot(0, 1));
+        if let ElectionMessage::Submit(msg) = &messages[1] {
+            let mut commit = msg.get_commit().unwrap();
+            commit.vote = Vote::Choice(0);
+            messages[1] = SubmitBallotMessage::new(tamper(msg.receipt(), &commit)).into();
+        }
+        assert_invalid_receipt(audit(messages), 1);
+    }
+
+    #[test]
+    fn changed_state_link_fails_audit() {
+        // Pointing a ballot at a different starting state could hide a ballot left out of the
+        // chain, so the link is covered by the seal too.
+        let mut messages = election(ballot(0, 1));
+        let init_state = match &messages[0] {
+            ElectionMessage::Init(msg) => msg.get_state().unwrap().state,
+            _ => unreachable!(),
+        };
+        if let ElectionMessage::Submit(msg) = &messages[2] {
+            let mut commit = msg.get_commit().unwrap();
+            commit.old_state = init_state;
+            messages[2] = SubmitBallotMessage::new(tamper(msg.receipt(), &commit)).into();
+        }
+        assert_invalid_receipt(audit(messages), 2);
+    }
+
+    #[test]
+    fn changed_tally_fails_audit() {
+        let mut messages = election(ballot(0, 1));
+        if let ElectionMessage::Freeze(msg) = &messages[3] {
+            let mut commit = msg.get_commit().unwrap();
+            commit.tally = vec![0, 2];
+            messages[3] = FreezeStationMessage::new(tamper(msg.receipt(), &commit)).into();
+        }
+        assert_invalid_receipt(audit(messages), 3);
+    }
+
+    #[test]
+    fn reordered_dropped_and_replayed_messages_fail_audit() {
+        let mut messages = election(ballot(0, 1));
+        messages.swap(1, 2);
+        assert_broken_chain(audit(messages), 1);
+
+        let mut messages = election(ballot(0, 1));
+        messages.remove(1);
+        assert_broken_chain(audit(messages), 1);
+
+        let mut messages = election(ballot(0, 1));
+        let replayed = ElectionMessage::from_bytes(&messages[1].to_bytes().unwrap()).unwrap();
+        messages.insert(2, replayed);
+        assert_broken_chain(audit(messages), 2);
+    }
+
+    #[test]
+    fn messages_from_another_election_fail_audit() {
+        let mut messages = election(ballot(0, 1));
+        let other = election(ballot(0, 0));
+        // The other election starts from the same state, so its init would fit, but its second
+        // ballot follows on from a different first ballot.
+        messages[0] = ElectionMessage::from_bytes(&other[0].to_bytes().unwrap()).unwrap();
+        messages[2] = ElectionMessage::from_bytes(&other[2].to_bytes().unwrap()).unwrap();
+        assert_broken_chain(audit(messages), 2);
+    }
+}
@@ -0,0 +1,181 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use risc0_zkvm::Receipt;
+use serde::{Deserialize, Serialize};
+use voting_machine_methods::{
+    FREEZE_ID, INIT_ID, NEW_ROUND_ID, SUBMIT_BATCH_ID, SUBMIT_ID, SUBMIT_SECRET_ID, TALLY_ID,
+};
+
+use crate::error::{Result, VotingMachineError};
+
+/// Version of the [Envelope] format written by this crate.
+pub const ENVELOPE_VERSION: u32 = 1;
+
+/// The guest methods that produce voting machine receipts.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum Method {
+    Init,
+    Submit,
+    SubmitBatch,
+    SubmitSecret,
+    Freeze,
+    NewRound,
+    Tally,
+}
+
+impl Method {
+    pub const ALL: [Method; 7] = [
+        Method::Init,
+        Method::Submit,
+        Method::SubmitBatch,
+        Method::SubmitSecret,
+        Method::Freeze,
+        Method::NewRound,
+        Method::Tally,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Method::Init => "init",
+            Method::Submit => "submit",
+            Method::SubmitBatch => "submit_batch",
+            Method::SubmitSecret => "submit_secret",
+            Method::Freeze => "freeze",
+            Method::NewRound => "new_round",
+            Method::Tally => "tally",
+        }
+    }
+
+    pub fn id(&self) -> &'static [u8] {
+        match self {
+            Method::Init => INIT_ID,
+            Method::Submit => SUBMIT_ID,
+            Method::SubmitBatch => SUBMIT_BATCH_ID,
+            Method::SubmitSecret => SUBMIT_SECRET_ID,
+            Method::Freeze => FREEZE_ID,
+            Method::NewRound => NEW_ROUND_ID,
+            Method::Tally => TALLY_ID,
+        }
+    }
+}
+
+/// Self-describing wire format for a voting machine receipt.
+///
+/// Alongside the receipt, an envelope names the method that produced it and carries that
+/// method's ID, so a verifier built from different guest code turns the message away up front
+/// rather than failing to verify it.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Envelope {
+    pub version: u32,
+    pub kind: Method,
+    pub method_id: Vec<u8>,
+    /// The receipt, serialized with bincode.
+    pub receipt: Vec<u8>,
+}
+
+impl Envelope {
+    pub fn new(kind: Method, receipt: &Receipt) -> Result<Self> {
+        Ok(Envelope {
+            version: ENVELOPE_VERSION,
+            kind,
+            method_id: kind.id().to_vec(),
+            receipt: bincode::serialize(receipt).map_err(VotingMachineError::decode)?,
+        })
+    }
+
+    /// Check that the envelope holds a receipt from the `expected` method, as built into this
+    /// crate, and take the receipt out. The receipt itself is not verified.
+    pub fn open(&self, expected: Method) -> Result<Receipt> {
+        if self.version != ENVELOPE_VERSION {
+            return Err(VotingMachineError::UnsupportedVersion {
+                version: self.version,
+            });
+        }
+        if self.kind != expected {
+            return Err(VotingMachineError::WrongMethod {
+                expected: expected.name(),
+                found: self.kind.name(),
+            });
+        }
+        if self.method_id != expected.id() {
+            return Err(VotingMachineError::MethodIdMismatch {
+                method: expected.name(),
+            });
+        }
+        bincode::deserialize(&self.receipt).map_err(VotingMachineError::decode)
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        bincode::serialize(self).map_err(VotingMachineError::decode)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        bincode::deserialize(bytes).map_err(VotingMachineError::decode)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ElectionMessage, InitMessage, SubmitBallotMessage};
+
+    #[test]
+    fn envelope_checks_version_and_method() {
+        let receipt = Receipt::new(&[1, 2, 3], &[4, 5]);
+        let envelope = Envelope::new(Method::Init, &receipt).unwrap();
+        let envelope = Envelope::from_bytes(&envelope.to_bytes().unwrap()).unwrap();
+        assert_eq!(
+            envelope.open(Method::Init).unwrap().journal,
+            receipt.journal
+        );
+        assert!(matches!(
+            envelope.open(Method::Submit),
+            Err(VotingMachineError::WrongMethod {
+                expected: "submit",
+                found: "init"
+            })
+        ));
+
+        let mut future = envelope.clone();
+        future.version += 1;
+        assert!(matches!(
+            future.open(Method::Init),
+            Err(VotingMachineError::UnsupportedVersion { .. })
+        ));
+
+        let mut rebuilt = envelope.clone();
+        rebuilt.method_id.push(0);
+        assert!(matches!(
+            rebuilt.open(Method::Init),
+            Err(VotingMachineError::MethodIdMismatch { method: "init" })
+        ));
+    }
+
+    #[test]
+    fn messages_serialize_as_envelopes() {
+        let msg = InitMessage::new(Receipt::new(&[7], &[8]));
+        let bytes = bincode::serialize(&msg).unwrap();
+        let envelope: Envelope = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(envelope.kind, Method::Init);
+
+        let msg: InitMessage = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(msg.receipt().journal, vec![7]);
+        assert!(bincode::deserialize::<SubmitBallotMessage>(&bytes).is_err());
+        match ElectionMessage::from_bytes(&bytes).unwrap() {
+            ElectionMessage::Init(msg) => assert_eq!(msg.receipt().seal, vec![8]),
+            msg => panic!("unexpected {:?} message", msg.kind()),
+        }
+    }
+}
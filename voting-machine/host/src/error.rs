@@ -0,0 +1,130 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fmt::{Display, Formatter};
+
+use risc0_zkp::core::sha::Digest;
+
+use crate::audit::AuditError;
+
+pub type Result<T> = std::result::Result<T, VotingMachineError>;
+
+#[derive(Debug)]
+pub enum VotingMachineError {
+    /// A journal, or the output of a guest, could not be decoded.
+    Decode { reason: String },
+    /// A receipt did not verify against any voting machine method.
+    InvalidReceipt { reason: String },
+    /// A receipt verified, but was produced by a different method than the one expected.
+    WrongMethod {
+        expected: &'static str,
+        found: &'static str,
+    },
+    /// A message envelope was written in a format version this crate does not understand.
+    UnsupportedVersion { version: u32 },
+    /// A message envelope carries a different method ID than this crate's build of the method,
+    /// so its receipt cannot be checked here.
+    MethodIdMismatch { method: &'static str },
+    /// A state digest did not match the state it was supposed to follow on from or lead to.
+    StateMismatch { expected: Digest, found: Digest },
+    /// A voter registry or weight table is held by a station whose state does not commit to one,
+    /// or is missing from a station whose state does.
+    NotCommitted { name: &'static str },
+    /// The prover could not be set up or failed to run.
+    Prover { reason: String },
+    /// Reading or writing a polling station's storage failed.
+    Storage { reason: String },
+    /// The receipts in a polling station's storage did not pass an audit.
+    Audit(AuditError),
+}
+
+impl VotingMachineError {
+    pub(crate) fn decode<E: Display>(err: E) -> Self {
+        VotingMachineError::Decode {
+            reason: err.to_string(),
+        }
+    }
+
+    pub(crate) fn prover<E: Display>(err: E) -> Self {
+        VotingMachineError::Prover {
+            reason: err.to_string(),
+        }
+    }
+
+    pub(crate) fn storage<E: Display>(err: E) -> Self {
+        VotingMachineError::Storage {
+            reason: err.to_string(),
+        }
+    }
+}
+
+impl Display for VotingMachineError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            VotingMachineError::Decode { reason } => write!(f, "failed to decode: {}", reason),
+            VotingMachineError::InvalidReceipt { reason } => {
+                write!(f, "receipt is invalid: {}", reason)
+            }
+            VotingMachineError::WrongMethod { expected, found } => write!(
+                f,
+                "expected a receipt from the {} method, but it is from {}",
+                expected, found
+            ),
+            VotingMachineError::UnsupportedVersion { version } => {
+                write!(f, "unsupported message envelope version {}", version)
+            }
+            VotingMachineError::MethodIdMismatch { method } => write!(
+                f,
+                "message was produced by a different build of the {} method",
+                method
+            ),
+            VotingMachineError::StateMismatch { expected, found } => {
+                write!(f, "expected state {:?}, found {:?}", expected, found)
+            }
+            VotingMachineError::NotCommitted { name } => {
+                write!(f, "{} does not match the state's commitments", name)
+            }
+            VotingMachineError::Prover { reason } => write!(f, "prover failed: {}", reason),
+            VotingMachineError::Storage { reason } => write!(f, "storage failed: {}", reason),
+            VotingMachineError::Audit(err) => write!(f, "audit failed: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for VotingMachineError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            VotingMachineError::Audit(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<AuditError> for VotingMachineError {
+    fn from(err: AuditError) -> Self {
+        VotingMachineError::Audit(err)
+    }
+}
+
+impl From<std::io::Error> for VotingMachineError {
+    fn from(err: std::io::Error) -> Self {
+        VotingMachineError::storage(err)
+    }
+}
+
+impl From<bincode::Error> for VotingMachineError {
+    fn from(err: bincode::Error) -> Self {
+        VotingMachineError::storage(err)
+    }
+}
@@ -12,14 +12,99 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+pub mod audit;
+pub mod envelope;
+pub mod error;
+pub mod store;
+
+use std::path::Path;
+use std::result::Result as StdResult;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use risc0_zkp::core::sha::Digest;
 use risc0_zkvm::serde::{from_slice, to_vec};
-use risc0_zkvm::{Prover, Receipt, Result};
+use risc0_zkvm::{Prover, Receipt};
+use serde::de::Error as _;
+use serde::ser::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use voting_machine_core::{
     Ballot, FreezeVotingMachineCommit, FreezeVotingMachineParams, FreezeVotingMachineResult,
-    InitializeVotingMachineCommit, SubmitBallotCommit, SubmitBallotParams, SubmitBallotResult,
-    VotingMachineState,
+    InitializeVotingMachineCommit, MerkleProof, NewRoundCommit, NewRoundParams, NewRoundResult,
+    PollWindow, SubmitBallotCommit, SubmitBallotParams, SubmitBallotResult, SubmitBatchCommit,
+    SubmitBatchParams, SubmitBatchResult, SubmitSecretBallotCommit, SubmitSecretBallotParams,
+    SubmitSecretBallotResult, TallyCommit, TallyParams, VoterRegistry, VoterSet,
+    VotingMachineState, WeightProof, WeightTable,
 };
-use voting_machine_methods::{FREEZE_ELF, FREEZE_ID, INIT_ELF, INIT_ID, SUBMIT_ELF, SUBMIT_ID};
+use voting_machine_methods::{
+    FREEZE_ELF, FREEZE_ID, INIT_ELF, INIT_ID, NEW_ROUND_ELF, NEW_ROUND_ID, SUBMIT_BATCH_ELF,
+    SUBMIT_BATCH_ID, SUBMIT_ELF, SUBMIT_ID, SUBMIT_SECRET_ELF, SUBMIT_SECRET_ID, TALLY_ELF,
+    TALLY_ID,
+};
+
+pub use crate::audit::{
+    AuditError, AuditReport, AuditedBallot, AuditedVote, ElectionAuditor, ElectionMessage,
+    MessageKind,
+};
+pub use crate::envelope::{Envelope, Method, ENVELOPE_VERSION};
+pub use crate::error::{Result, VotingMachineError};
+use crate::store::{Snapshot, Store};
+
+/// Constructors, envelope conversions and serde support shared by the message types. Each message
+/// is serialized as an [Envelope] for the method that produced it.
+macro_rules! impl_message {
+    ($message:ident, $method:expr) => {
+        impl $message {
+            pub fn new(receipt: Receipt) -> Self {
+                $message { receipt }
+            }
+
+            pub fn receipt(&self) -> &Receipt {
+                &self.receipt
+            }
+
+            pub fn to_envelope(&self) -> Result<Envelope> {
+                Envelope::new($method, &self.receipt)
+            }
+
+            pub fn from_envelope(envelope: &Envelope) -> Result<Self> {
+                Ok($message {
+                    receipt: envelope.open($method)?,
+                })
+            }
+
+            pub fn to_bytes(&self) -> Result<Vec<u8>> {
+                self.to_envelope()?.to_bytes()
+            }
+
+            pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+                Self::from_envelope(&Envelope::from_bytes(bytes)?)
+            }
+        }
+
+        impl Serialize for $message {
+            fn serialize<S: Serializer>(&self, serializer: S) -> StdResult<S::Ok, S::Error> {
+                self.to_envelope()
+                    .map_err(S::Error::custom)?
+                    .serialize(serializer)
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $message {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> StdResult<Self, D::Error> {
+                let envelope = Envelope::deserialize(deserializer)?;
+                Self::from_envelope(&envelope).map_err(D::Error::custom)
+            }
+        }
+    };
+}
+
+impl_message!(InitMessage, Method::Init);
+impl_message!(SubmitBallotMessage, Method::Submit);
+impl_message!(SubmitBatchMessage, Method::SubmitBatch);
+impl_message!(SubmitSecretBallotMessage, Method::SubmitSecret);
+impl_message!(FreezeStationMessage, Method::Freeze);
+impl_message!(NewRoundMessage, Method::NewRound);
+impl_message!(TallyMessage, Method::Tally);
 
 pub struct InitMessage {
     receipt: Receipt,
@@ -27,11 +112,11 @@ pub struct InitMessage {
 
 impl InitMessage {
     pub fn get_state(&self) -> Result<InitializeVotingMachineCommit> {
-        Ok(from_slice(&self.receipt.journal).unwrap())
+        from_slice(&self.receipt.journal).map_err(VotingMachineError::decode)
     }
 
     pub fn verify_and_get_commit(&self) -> Result<InitializeVotingMachineCommit> {
-        self.receipt.verify(INIT_ID)?;
+        verify(&self.receipt, Method::Init)?;
         self.get_state()
     }
 }
@@ -42,11 +127,41 @@ pub struct SubmitBallotMessage {
 
 impl SubmitBallotMessage {
     pub fn get_commit(&self) -> Result<SubmitBallotCommit> {
-        Ok(from_slice(&self.receipt.journal).unwrap())
+        from_slice(&self.receipt.journal).map_err(VotingMachineError::decode)
     }
 
     pub fn verify_and_get_commit(&self) -> Result<SubmitBallotCommit> {
-        self.receipt.verify(SUBMIT_ID)?;
+        verify(&self.receipt, Method::Submit)?;
+        self.get_commit()
+    }
+}
+
+pub struct SubmitBatchMessage {
+    receipt: Receipt,
+}
+
+impl SubmitBatchMessage {
+    pub fn get_commit(&self) -> Result<SubmitBatchCommit> {
+        from_slice(&self.receipt.journal).map_err(VotingMachineError::decode)
+    }
+
+    pub fn verify_and_get_commit(&self) -> Result<SubmitBatchCommit> {
+        verify(&self.receipt, Method::SubmitBatch)?;
+        self.get_commit()
+    }
+}
+
+pub struct SubmitSecretBallotMessage {
+    receipt: Receipt,
+}
+
+impl SubmitSecretBallotMessage {
+    pub fn get_commit(&self) -> Result<SubmitSecretBallotCommit> {
+        from_slice(&self.receipt.journal).map_err(VotingMachineError::decode)
+    }
+
+    pub fn verify_and_get_commit(&self) -> Result<SubmitSecretBallotCommit> {
+        verify(&self.receipt, Method::SubmitSecret)?;
         self.get_commit()
     }
 }
@@ -57,11 +172,41 @@ pub struct FreezeStationMessage {
 
 impl FreezeStationMessage {
     pub fn get_commit(&self) -> Result<FreezeVotingMachineCommit> {
-        Ok(from_slice(&self.receipt.journal).unwrap())
+        from_slice(&self.receipt.journal).map_err(VotingMachineError::decode)
     }
 
     pub fn verify_and_get_commit(&self) -> Result<FreezeVotingMachineCommit> {
-        self.receipt.verify(FREEZE_ID)?;
+        verify(&self.receipt, Method::Freeze)?;
+        self.get_commit()
+    }
+}
+
+pub struct NewRoundMessage {
+    receipt: Receipt,
+}
+
+impl NewRoundMessage {
+    pub fn get_commit(&self) -> Result<NewRoundCommit> {
+        from_slice(&self.receipt.journal).map_err(VotingMachineError::decode)
+    }
+
+    pub fn verify_and_get_commit(&self) -> Result<NewRoundCommit> {
+        verify(&self.receipt, Method::NewRound)?;
+        self.get_commit()
+    }
+}
+
+pub struct TallyMessage {
+    receipt: Receipt,
+}
+
+impl TallyMessage {
+    pub fn get_commit(&self) -> Result<TallyCommit> {
+        from_slice(&self.receipt.journal).map_err(VotingMachineError::decode)
+    }
+
+    pub fn verify_and_get_commit(&self) -> Result<TallyCommit> {
+        verify(&self.receipt, Method::Tally)?;
         self.get_commit()
     }
 }
@@ -69,54 +214,380 @@ impl FreezeStationMessage {
 #[derive(Debug)]
 pub struct PollingStation {
     state: VotingMachineState,
+    voters: VoterSet,
+    registry: Option<VoterRegistry>,
+    weights: Option<WeightTable>,
+    store: Option<Store>,
 }
 
 impl PollingStation {
-    pub fn new(state: VotingMachineState) -> Self {
-        PollingStation { state }
+    /// Start a polling station for `state`, which must commit to `voters`.
+    ///
+    /// Fails with [VotingMachineError::StateMismatch] if the voter set's root is not the state's
+    /// voter root.
+    pub fn new(state: VotingMachineState, voters: VoterSet) -> Result<Self> {
+        check_root("voter set", Some(&state.voter_root), voters.root())?;
+        Ok(PollingStation {
+            state,
+            voters,
+            registry: None,
+            weights: None,
+            store: None,
+        })
+    }
+
+    /// Keep the station's state and every message it publishes in the directory at `path`, so
+    /// that it can be picked up again with [PollingStation::open]. Any registry or weight table
+    /// must already be attached.
+    pub fn with_storage(mut self, path: impl AsRef<Path>) -> Result<Self> {
+        self.store = Some(Store::create(path, &self.snapshot())?);
+        Ok(self)
+    }
+
+    /// Resume a polling station from the directory it was stored in.
+    ///
+    /// Every receipt in the log is verified and checked to follow on from the one before it, and
+    /// the saved state must be the one the last receipt ended at.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let store = Store::open(path)?;
+        let mut auditor = ElectionAuditor::new();
+        for msg in store.messages()? {
+            auditor.push(msg);
+        }
+        let snapshot = match auditor.audit_partial() {
+            Ok(report) => store.load_snapshot(Some(&report.final_state))?,
+            // Nothing has been published yet, so there is nothing to check the state against.
+            Err(AuditError::Empty) => store.load_snapshot(None)?,
+            Err(err) => return Err(err.into()),
+        };
+        log::info!("resumed polling station from {}", store.dir().display());
+        let station = PollingStation::new(snapshot.state, snapshot.voters)?;
+        let station = match snapshot.registry {
+            Some(registry) => station.with_registry(registry)?,
+            None => station,
+        };
+        let station = match snapshot.weights {
+            Some(weights) => station.with_weights(weights)?,
+            None => station,
+        };
+        Ok(PollingStation {
+            store: Some(store),
+            ..station
+        })
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            state: self.state.clone(),
+            voters: self.voters.clone(),
+            registry: self.registry.clone(),
+            weights: self.weights.clone(),
+        }
+    }
+
+    /// Save a newly published message to storage, if the station has any.
+    fn record(&self, kind: MessageKind, receipt: &Receipt) -> Result<()> {
+        match &self.store {
+            Some(store) => store.append(kind, receipt, &self.snapshot()),
+            None => Ok(()),
+        }
+    }
+
+    /// Attach the voter registry that the state's registry root commits to.
+    ///
+    /// Fails with [VotingMachineError::StateMismatch] if the registry's root is not the state's
+    /// registry root, or [VotingMachineError::NotCommitted] if the state has no registry root.
+    pub fn with_registry(mut self, registry: VoterRegistry) -> Result<Self> {
+        check_root(
+            "voter registry",
+            self.state.registry_root.as_ref(),
+            registry.root(),
+        )?;
+        self.registry = Some(registry);
+        Ok(self)
+    }
+
+    fn registry_proof(&self, voter: u32) -> Option<MerkleProof> {
+        self.registry.as_ref().map(|registry| registry.prove(voter))
+    }
+
+    /// Attach the weight table that the state's weight root commits to.
+    ///
+    /// Fails with [VotingMachineError::StateMismatch] if the table's root is not the state's
+    /// weight root, or [VotingMachineError::NotCommitted] if the state has no weight root.
+    pub fn with_weights(mut self, weights: WeightTable) -> Result<Self> {
+        check_root(
+            "weight table",
+            self.state.weight_root.as_ref(),
+            weights.root(),
+        )?;
+        self.weights = Some(weights);
+        Ok(self)
+    }
+
+    fn weight_proof(&self, voter: u32) -> Option<WeightProof> {
+        self.weights.as_ref().map(|weights| weights.prove(voter))
+    }
+
+    /// Fail with [VotingMachineError::NotCommitted] if the state commits to a voter registry or
+    /// weight table that was never attached, since ballots can't be proven without it.
+    fn check_tables(&self) -> Result<()> {
+        if self.state.registry_root.is_some() && self.registry.is_none() {
+            return Err(VotingMachineError::NotCommitted {
+                name: "voter registry",
+            });
+        }
+        if self.state.weight_root.is_some() && self.weights.is_none() {
+            return Err(VotingMachineError::NotCommitted {
+                name: "weight table",
+            });
+        }
+        Ok(())
     }
 
     pub fn init(&self) -> Result<InitMessage> {
         log::info!("init");
-        let mut prover = Prover::new(INIT_ELF, INIT_ID)?;
-        let vec = to_vec(&self.state).unwrap();
-        prover.add_input_u32_slice(vec.as_slice());
-        let receipt = prover.run()?;
-        Ok(InitMessage { receipt })
+        let (receipt, _) = prove(INIT_ELF, INIT_ID, &self.state)?;
+        let msg = InitMessage { receipt };
+        check_state(&msg.get_state()?.state, &self.state)?;
+        self.record(MessageKind::Init, &msg.receipt)?;
+        Ok(msg)
     }
 
+    /// Submit a ballot, cast now.
     pub fn submit(&mut self, ballot: &Ballot) -> Result<SubmitBallotMessage> {
-        log::info!("submit: {:?}", ballot);
-        let params = SubmitBallotParams::new(self.state.clone(), ballot.clone());
-        let mut prover = Prover::new(SUBMIT_ELF, SUBMIT_ID)?;
-        let vec = to_vec(&params).unwrap();
-        prover.add_input_u32_slice(vec.as_slice());
-        let receipt = prover.run()?;
-        let vec = prover.get_output_u32_vec()?;
+        self.submit_at(ballot, now())
+    }
+
+    /// Submit a ballot cast at `time`, in seconds since the Unix epoch.
+    pub fn submit_at(&mut self, ballot: &Ballot, time: u64) -> Result<SubmitBallotMessage> {
+        log::info!("submit at {}: {:?}", time, ballot);
+        self.check_tables()?;
+        let params = SubmitBallotParams::new(
+            self.state.clone(),
+            ballot.clone(),
+            self.voters.prove(ballot.voter),
+            self.registry_proof(ballot.voter),
+            self.weight_proof(ballot.voter),
+            time,
+        );
+        let (receipt, vec) = prove(SUBMIT_ELF, SUBMIT_ID, &params)?;
         log::info!("{:?}", vec);
         let result = from_slice::<SubmitBallotResult>(&vec);
         log::info!("{:?}", result);
-        self.state = result.unwrap().state.clone();
-        Ok(SubmitBallotMessage { receipt })
+        let result = result.map_err(VotingMachineError::decode)?;
+        let msg = SubmitBallotMessage { receipt };
+        check_state(&msg.get_commit()?.new_state, &result.state)?;
+        if result.vote_counted {
+            self.voters.insert(ballot.voter);
+        }
+        self.state = result.state;
+        self.record(MessageKind::Submit, &msg.receipt)?;
+        Ok(msg)
+    }
+
+    /// Submit several ballots in one receipt, all cast now.
+    pub fn submit_batch(&mut self, ballots: &[Ballot]) -> Result<SubmitBatchMessage> {
+        self.submit_batch_at(ballots, now())
+    }
+
+    /// Submit several ballots in one receipt, all cast at `time`.
+    pub fn submit_batch_at(&mut self, ballots: &[Ballot], time: u64) -> Result<SubmitBatchMessage> {
+        log::info!("submit batch at {}: {} ballots", time, ballots.len());
+        self.check_tables()?;
+        // Each voter proof has to reflect the ballots before it, so replay the batch against a
+        // copy of the state to build them.
+        let mut state = self.state.clone();
+        let mut voters = self.voters.clone();
+        let mut voter_proofs = Vec::with_capacity(ballots.len());
+        let mut registry_proofs = Vec::with_capacity(ballots.len());
+        let mut weight_proofs = Vec::with_capacity(ballots.len());
+        for ballot in ballots {
+            let voter_proof = voters.prove(ballot.voter);
+            let registry_proof = self.registry_proof(ballot.voter);
+            let weight_proof = self.weight_proof(ballot.voter);
+            if state.vote(
+                ballot,
+                &voter_proof,
+                registry_proof.as_ref(),
+                weight_proof.as_ref(),
+                time,
+            ) {
+                voters.insert(ballot.voter);
+            }
+            voter_proofs.push(voter_proof);
+            registry_proofs.push(registry_proof);
+            weight_proofs.push(weight_proof);
+        }
+        let params = SubmitBatchParams::new(
+            self.state.clone(),
+            ballots.to_vec(),
+            voter_proofs,
+            registry_proofs,
+            weight_proofs,
+            time,
+        );
+        let (receipt, vec) = prove(SUBMIT_BATCH_ELF, SUBMIT_BATCH_ID, &params)?;
+        let result = from_slice::<SubmitBatchResult>(&vec).map_err(VotingMachineError::decode)?;
+        log::info!("{:?}", result.vote_counted);
+        let msg = SubmitBatchMessage { receipt };
+        check_state(&msg.get_commit()?.new_state, &result.state)?;
+        self.state = result.state;
+        self.voters = voters;
+        self.record(MessageKind::SubmitBatch, &msg.receipt)?;
+        Ok(msg)
+    }
+
+    /// Submit a ballot in a secret-ballot election. The voter keeps `nonce` private and can use it
+    /// to recompute the nullifier that identifies the receipt for their ballot.
+    pub fn submit_secret(
+        &mut self,
+        ballot: &Ballot,
+        nonce: Digest,
+    ) -> Result<SubmitSecretBallotMessage> {
+        self.submit_secret_at(ballot, nonce, now())
+    }
+
+    /// Submit a secret ballot cast at `time`.
+    pub fn submit_secret_at(
+        &mut self,
+        ballot: &Ballot,
+        nonce: Digest,
+        time: u64,
+    ) -> Result<SubmitSecretBallotMessage> {
+        log::info!("submit secret ballot at {}", time);
+        self.check_tables()?;
+        let params = SubmitSecretBallotParams::new(
+            self.state.clone(),
+            ballot.clone(),
+            self.voters.prove(ballot.voter),
+            self.registry_proof(ballot.voter),
+            self.weight_proof(ballot.voter),
+            nonce,
+            time,
+        );
+        let (receipt, vec) = prove(SUBMIT_SECRET_ELF, SUBMIT_SECRET_ID, &params)?;
+        let result =
+            from_slice::<SubmitSecretBallotResult>(&vec).map_err(VotingMachineError::decode)?;
+        let msg = SubmitSecretBallotMessage { receipt };
+        check_state(&msg.get_commit()?.new_state, &result.state)?;
+        if result.vote_counted {
+            self.voters.insert(ballot.voter);
+        }
+        self.state = result.state;
+        self.record(MessageKind::SubmitSecret, &msg.receipt)?;
+        Ok(msg)
     }
 
     pub fn freeze(&mut self) -> Result<FreezeStationMessage> {
         log::info!("freeze");
         let params = FreezeVotingMachineParams::new(self.state.clone());
-        let mut prover = Prover::new(FREEZE_ELF, FREEZE_ID)?;
-        let vec = to_vec(&params).unwrap();
-        prover.add_input_u32_slice(vec.as_slice());
-        let receipt = prover.run()?;
-        let slice = prover.get_output_u32_vec()?;
-        let result = from_slice::<FreezeVotingMachineResult>(&slice).unwrap();
-        self.state = result.state.clone();
-        Ok(FreezeStationMessage { receipt })
+        let (receipt, slice) = prove(FREEZE_ELF, FREEZE_ID, &params)?;
+        let result =
+            from_slice::<FreezeVotingMachineResult>(&slice).map_err(VotingMachineError::decode)?;
+        let msg = FreezeStationMessage { receipt };
+        check_state(&msg.get_commit()?.new_state, &result.state)?;
+        self.state = result.state;
+        self.record(MessageKind::Freeze, &msg.receipt)?;
+        Ok(msg)
+    }
+
+    /// Start the next round of a frozen election, accepting ballots within `window`. Every voter
+    /// may vote again in the new round.
+    pub fn new_round(&mut self, window: Option<PollWindow>) -> Result<NewRoundMessage> {
+        log::info!("new round");
+        let params = NewRoundParams::new(self.state.clone(), window);
+        let (receipt, vec) = prove(NEW_ROUND_ELF, NEW_ROUND_ID, &params)?;
+        let result = from_slice::<NewRoundResult>(&vec).map_err(VotingMachineError::decode)?;
+        let msg = NewRoundMessage { receipt };
+        check_state(&msg.get_commit()?.new_state, &result.state)?;
+        self.state = result.state;
+        self.voters = VoterSet::new();
+        self.record(MessageKind::NewRound, &msg.receipt)?;
+        Ok(msg)
+    }
+
+    pub fn tally(&self) -> Result<TallyMessage> {
+        log::info!("tally");
+        let params = TallyParams::new(self.state.clone());
+        let (receipt, _) = prove(TALLY_ELF, TALLY_ID, &params)?;
+        let msg = TallyMessage { receipt };
+        check_state(&msg.get_commit()?.state, &self.state)?;
+        Ok(msg)
+    }
+}
+
+/// The current time, in seconds since the Unix epoch.
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system clock is set before the Unix epoch")
+        .as_secs()
+}
+
+/// Run a guest method on the given input, returning its receipt and the output it wrote.
+fn prove<T: Serialize>(elf: &[u8], id: &[u8], input: &T) -> Result<(Receipt, Vec<u32>)> {
+    let mut prover = Prover::new(elf, id).map_err(VotingMachineError::prover)?;
+    let vec = to_vec(input).map_err(VotingMachineError::prover)?;
+    prover.add_input_u32_slice(vec.as_slice());
+    let receipt = prover.run().map_err(VotingMachineError::prover)?;
+    let output = prover
+        .get_output_u32_vec()
+        .map_err(VotingMachineError::prover)?;
+    Ok((receipt, output))
+}
+
+/// Check that the state a guest handed back to the host is the one its receipt commits to.
+fn check_state(committed: &Digest, state: &VotingMachineState) -> Result<()> {
+    let digest = state.digest();
+    if committed != &digest {
+        return Err(VotingMachineError::StateMismatch {
+            expected: *committed,
+            found: digest,
+        });
+    }
+    Ok(())
+}
+
+/// Check a voter set, registry or weight table against the root its state commits to, if any.
+pub(crate) fn check_root(
+    name: &'static str,
+    committed: Option<&Digest>,
+    root: Digest,
+) -> Result<()> {
+    match committed {
+        Some(committed) if committed == &root => Ok(()),
+        Some(committed) => Err(VotingMachineError::StateMismatch {
+            expected: *committed,
+            found: root,
+        }),
+        None => Err(VotingMachineError::NotCommitted { name }),
     }
 }
 
+/// Verify a receipt against the method it is expected to come from. If it does not verify, check
+/// whether it came from one of the other methods instead, to give a clearer error.
+fn verify(receipt: &Receipt, method: Method) -> Result<()> {
+    receipt.verify(method.id()).map_err(|err| {
+        let found = Method::ALL
+            .iter()
+            .find(|other| **other != method && receipt.verify(other.id()).is_ok());
+        match found {
+            Some(found) => VotingMachineError::WrongMethod {
+                expected: method.name(),
+                found: found.name(),
+            },
+            None => VotingMachineError::InvalidReceipt {
+                reason: err.to_string(),
+            },
+        }
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use log::LevelFilter;
+    use voting_machine_core::{DecisionRule, Outcome, Passphrase, PollWindow, Vote};
 
     use super::*;
 
@@ -127,37 +598,46 @@ mod tests {
 
     #[test]
     fn protocol() {
-        let polling_station_state = VotingMachineState {
-            polls_open: true,
-            voter_bitfield: 0,
-            count: 0,
-        };
+        let voters = VoterSet::new();
+        // Option 0 is "no" and option 1 is "yes".
+        let polling_station_state = VotingMachineState::new(2, voters.root());
 
-        let mut polling_station = PollingStation::new(polling_station_state);
+        let mut polling_station = PollingStation::new(polling_station_state, voters).unwrap();
 
         let ballot1 = Ballot {
             voter: 0,
-            vote_yes: false,
+            vote: Vote::Choice(0),
+            passphrase: None,
         };
         let ballot2 = Ballot {
             voter: 1,
-            vote_yes: true,
+            vote: Vote::Choice(1),
+            passphrase: None,
         };
         let ballot3 = Ballot {
             voter: 2,
-            vote_yes: true,
+            vote: Vote::Choice(1),
+            passphrase: None,
         };
         let ballot4 = Ballot {
             voter: 1,
-            vote_yes: false,
+            vote: Vote::Choice(0),
+            passphrase: None,
         };
         let ballot5 = Ballot {
             voter: 3,
-            vote_yes: false,
+            vote: Vote::Choice(0),
+            passphrase: None,
         };
         let ballot6 = Ballot {
             voter: 4,
-            vote_yes: true,
+            vote: Vote::Choice(1),
+            passphrase: None,
+        };
+        let ballot7 = Ballot {
+            voter: 40_000,
+            vote: Vote::Choice(1),
+            passphrase: None,
         };
 
         let init_msg = polling_station.init().unwrap();
@@ -166,10 +646,11 @@ mod tests {
         let ballot_msg3 = polling_station.submit(&ballot3).unwrap();
         let ballot_msg4 = polling_station.submit(&ballot4).unwrap();
         let ballot_msg5 = polling_station.submit(&ballot5).unwrap();
+        let ballot_msg7 = polling_station.submit(&ballot7).unwrap();
         let close_msg = polling_station.freeze().unwrap();
         let ballot_msg6 = polling_station.submit(&ballot6).unwrap();
 
-        assert_eq!(polling_station.state.count, 2);
+        assert_eq!(polling_station.state.tally, vec![2, 3]);
 
         let init_state = init_msg.verify_and_get_commit();
         let ballot_commit1 = ballot_msg1.verify_and_get_commit();
@@ -177,6 +658,7 @@ mod tests {
         let ballot_commit3 = ballot_msg3.verify_and_get_commit();
         let ballot_commit4 = ballot_msg4.verify_and_get_commit();
         let ballot_commit5 = ballot_msg5.verify_and_get_commit();
+        let ballot_commit7 = ballot_msg7.verify_and_get_commit();
         let close_state = close_msg.verify_and_get_commit();
         let ballot_commit6 = ballot_msg6.verify_and_get_commit();
 
@@ -191,9 +673,589 @@ mod tests {
         log::info!("ballot 4 commit: {:?}", ballot_commit4);
         log::info!("ballot 5: {:?}", ballot5);
         log::info!("ballot 5 commit: {:?}", ballot_commit5);
+        log::info!("ballot 7: {:?}", ballot7);
+        log::info!("ballot 7 commit: {:?}", ballot_commit7);
         log::info!("freeze commit: {:?}", close_state);
         log::info!("ballot 6: {:?}", ballot6);
         log::info!("ballot 6 commit: {:?}", ballot_commit6);
-        log::info!("Final vote count: {:?}", polling_station.state.count);
+        log::info!("Final tally: {:?}", polling_station.state.tally);
+    }
+
+    #[test]
+    fn audit() {
+        let voters = VoterSet::new();
+        let polling_station_state = VotingMachineState::new(2, voters.root());
+        let mut polling_station = PollingStation::new(polling_station_state, voters).unwrap();
+
+        let ballot1 = Ballot {
+            voter: 0,
+            vote: Vote::Choice(1),
+            passphrase: None,
+        };
+        let ballot2 = Ballot {
+            voter: 1,
+            vote: Vote::Choice(0),
+            passphrase: None,
+        };
+        let ballot3 = Ballot {
+            voter: 2,
+            vote: Vote::Choice(1),
+            passphrase: None,
+        };
+
+        let mut auditor = ElectionAuditor::new();
+        auditor.push(polling_station.init().unwrap());
+        auditor.push(polling_station.submit(&ballot1).unwrap());
+        assert_eq!(
+            auditor.audit().unwrap_err(),
+            AuditError::MissingFreeze {
+                kind: MessageKind::Submit
+            }
+        );
+        // Leave the receipt for the second ballot out of the published messages.
+        polling_station.submit(&ballot2).unwrap();
+        auditor.push(
+            polling_station
+                .submit_batch(std::slice::from_ref(&ballot3))
+                .unwrap(),
+        );
+        auditor.push(polling_station.freeze().unwrap());
+        match auditor.audit().unwrap_err() {
+            AuditError::BrokenChain { index, kind, .. } => {
+                assert_eq!(index, 2);
+                assert_eq!(kind, MessageKind::SubmitBatch);
+            }
+            err => panic!("unexpected audit error: {}", err),
+        }
+
+        let mut polling_station = PollingStation::new(
+            VotingMachineState::new(2, VoterSet::new().root()),
+            VoterSet::new(),
+        )
+        .unwrap();
+        let mut auditor = ElectionAuditor::new();
+        auditor.push(polling_station.init().unwrap());
+        auditor.push(polling_station.submit(&ballot1).unwrap());
+        auditor.push(polling_station.submit(&ballot1).unwrap());
+        auditor.push(polling_station.submit_batch(&[ballot2, ballot3]).unwrap());
+        let close_msg = polling_station.freeze().unwrap();
+        let close_commit = close_msg.get_commit().unwrap();
+        auditor.push(close_msg);
+        let report = auditor.audit().unwrap();
+        assert_eq!(report.final_state, close_commit.new_state);
+        assert_eq!(report.tally, vec![1, 2]);
+        assert_eq!(report.ballots.len(), 4);
+        assert_eq!(report.ballots_counted(), 3);
+        log::info!("audit report: {:?}", report);
+    }
+
+    #[test]
+    fn secret_ballot() {
+        let voters = VoterSet::new();
+        let ballot_salt = Digest::new([0x5a17; 8]);
+        let polling_station_state = VotingMachineState::new_secret(2, voters.root(), ballot_salt);
+        let mut polling_station = PollingStation::new(polling_station_state, voters).unwrap();
+
+        let ballot1 = Ballot {
+            voter: 0,
+            vote: Vote::Choice(1),
+            passphrase: None,
+        };
+        let ballot2 = Ballot {
+            voter: 1,
+            vote: Vote::Choice(0),
+            passphrase: None,
+        };
+        let ballot3 = Ballot {
+            voter: 2,
+            vote: Vote::Choice(1),
+            passphrase: None,
+        };
+        let nonce1 = Digest::new([1; 8]);
+
+        let mut auditor = ElectionAuditor::new();
+        let init_msg = polling_station.init().unwrap();
+        assert!(init_msg.get_state().unwrap().secret_ballot);
+        auditor.push(init_msg);
+        let ballot_msg1 = polling_station.submit_secret(&ballot1, nonce1).unwrap();
+        let ballot_commit1 = ballot_msg1.verify_and_get_commit().unwrap();
+        auditor.push(ballot_msg1);
+        auditor.push(
+            polling_station
+                .submit_secret(&ballot2, Digest::new([2; 8]))
+                .unwrap(),
+        );
+        auditor.push(
+            polling_station
+                .submit_secret(&ballot3, Digest::new([3; 8]))
+                .unwrap(),
+        );
+        // Public submissions are refused once the election is secret.
+        assert!(polling_station.submit(&ballot3).is_err());
+        auditor.push(polling_station.freeze().unwrap());
+
+        assert_eq!(
+            ballot_commit1.nullifier,
+            voting_machine_core::secret::nullifier(ballot1.voter, &nonce1)
+        );
+        let report = auditor.audit().unwrap();
+        assert_eq!(report.tally, vec![1, 2]);
+        assert_eq!(report.ballots_counted(), 3);
+        assert_eq!(
+            report.ballots[0].vote,
+            AuditedVote::Secret {
+                nullifier: ballot_commit1.nullifier
+            }
+        );
+        log::info!("secret ballot commit: {:?}", ballot_commit1);
+    }
+
+    #[test]
+    fn batch() {
+        let voters = VoterSet::new();
+        let polling_station_state = VotingMachineState::new(2, voters.root());
+        let mut polling_station = PollingStation::new(polling_station_state, voters).unwrap();
+
+        let ballots: Vec<Ballot> = [(0, 1), (1, 0), (0, 0), (2, 1), (3, 2), (50_000, 1)]
+            .into_iter()
+            .map(|(voter, option)| Ballot {
+                voter,
+                vote: Vote::Choice(option),
+                passphrase: None,
+            })
+            .collect();
+
+        let init_commit = polling_station
+            .init()
+            .unwrap()
+            .verify_and_get_commit()
+            .unwrap();
+        let batch_commit = polling_station
+            .submit_batch(&ballots[..3])
+            .unwrap()
+            .verify_and_get_commit()
+            .unwrap();
+        let next_batch_commit = polling_station
+            .submit_batch(&ballots[3..])
+            .unwrap()
+            .verify_and_get_commit()
+            .unwrap();
+
+        assert_eq!(batch_commit.old_state, init_commit.state);
+        assert_eq!(batch_commit.vote_counted, vec![true, true, false]);
+        assert_eq!(next_batch_commit.old_state, batch_commit.new_state);
+        assert_eq!(next_batch_commit.vote_counted, vec![true, false, true]);
+        assert_eq!(polling_station.state.tally, vec![1, 3]);
+        log::info!("batch commit: {:?}", batch_commit);
+        log::info!("next batch commit: {:?}", next_batch_commit);
+    }
+
+    #[test]
+    fn ranked_choice() {
+        let voters = VoterSet::new();
+        let polling_station_state = VotingMachineState::new(3, voters.root());
+        let mut polling_station = PollingStation::new(polling_station_state, voters).unwrap();
+
+        let ballots = [
+            Vote::Ranked(vec![0]),
+            Vote::Ranked(vec![0, 1]),
+            Vote::Ranked(vec![1, 2]),
+            Vote::Choice(1),
+            Vote::Ranked(vec![2, 1]),
+        ];
+
+        polling_station.init().unwrap();
+        for (voter, vote) in ballots.into_iter().enumerate() {
+            let ballot = Ballot {
+                voter: voter as u32,
+                vote,
+                passphrase: None,
+            };
+            polling_station.submit(&ballot).unwrap();
+        }
+        let close_commit = polling_station
+            .freeze()
+            .unwrap()
+            .verify_and_get_commit()
+            .unwrap();
+        let tally_commit = polling_station
+            .tally()
+            .unwrap()
+            .verify_and_get_commit()
+            .unwrap();
+
+        // Option 2 is eliminated first and its ballot transfers to option 1.
+        assert_eq!(close_commit.tally, vec![2, 2, 1]);
+        assert_eq!(tally_commit.state, close_commit.new_state);
+        assert_eq!(tally_commit.tally, close_commit.tally);
+        assert_eq!(tally_commit.rounds, vec![vec![2, 2, 1], vec![2, 3, 0]]);
+        assert_eq!(tally_commit.winner, Some(1));
+        log::info!("tally commit: {:?}", tally_commit);
+    }
+
+    #[test]
+    fn decided_outcome() {
+        let voters = VoterSet::new();
+        let mut polling_station_state = VotingMachineState::new(2, voters.root());
+        polling_station_state.decision_rule = Some(DecisionRule::new(1, 3, 1, 2));
+        let mut polling_station = PollingStation::new(polling_station_state, voters).unwrap();
+
+        let mut auditor = ElectionAuditor::new();
+        let init_msg = polling_station.init().unwrap();
+        let init_state = init_msg.verify_and_get_commit().unwrap();
+        assert_eq!(
+            init_state.decision_rule,
+            Some(DecisionRule::new(1, 3, 1, 2))
+        );
+        auditor.push(init_msg);
+        for (voter, option) in [(0, 1), (1, 0), (2, 1)] {
+            let ballot = Ballot {
+                voter,
+                vote: Vote::Choice(option),
+                passphrase: None,
+            };
+            auditor.push(polling_station.submit(&ballot).unwrap());
+        }
+        let freeze_msg = polling_station.freeze().unwrap();
+        let freeze_commit = freeze_msg.verify_and_get_commit().unwrap();
+        assert_eq!(freeze_commit.outcome, Some(Outcome::Passed));
+        auditor.push(freeze_msg);
+        assert_eq!(auditor.audit().unwrap().outcome, Some(Outcome::Passed));
+
+        // Without enough voters in the next round, the motion lapses.
+        polling_station.new_round(None).unwrap();
+        let freeze_commit = polling_station
+            .freeze()
+            .unwrap()
+            .verify_and_get_commit()
+            .unwrap();
+        assert_eq!(freeze_commit.outcome, Some(Outcome::NoQuorum));
+    }
+
+    #[test]
+    fn weighted_voting() {
+        let mut weights = WeightTable::new();
+        weights.set(0, 60);
+        weights.set(1, 30);
+        weights.set(2, 10);
+
+        let voters = VoterSet::new();
+        let mut polling_station_state = VotingMachineState::new(2, voters.root());
+        polling_station_state.weight_root = Some(weights.root());
+        let mut polling_station = PollingStation::new(polling_station_state, voters)
+            .unwrap()
+            .with_weights(weights.clone())
+            .unwrap();
+
+        let init_state = polling_station.init().unwrap().get_state().unwrap();
+        assert_eq!(init_state.weight_root, Some(weights.root()));
+
+        let commit = polling_station
+            .submit(&Ballot {
+                voter: 0,
+                vote: Vote::Choice(0),
+                passphrase: None,
+            })
+            .unwrap()
+            .verify_and_get_commit()
+            .unwrap();
+        assert_eq!(commit.weight, 60);
+        assert!(commit.vote_counted);
+
+        let batch = [1, 2, 3].map(|voter| Ballot {
+            voter,
+            vote: Vote::Choice(1),
+            passphrase: None,
+        });
+        let batch_commit = polling_station
+            .submit_batch(&batch)
+            .unwrap()
+            .verify_and_get_commit()
+            .unwrap();
+        assert_eq!(batch_commit.weights, vec![30, 10, 0]);
+        assert_eq!(batch_commit.vote_counted, vec![true, true, false]);
+
+        let freeze_commit = polling_station
+            .freeze()
+            .unwrap()
+            .verify_and_get_commit()
+            .unwrap();
+        assert_eq!(freeze_commit.tally, vec![60, 40]);
+    }
+
+    #[test]
+    fn registered_voters() {
+        let alice = Passphrase { pass: [1; 32] };
+        let bob = Passphrase { pass: [2; 32] };
+        let mut registry = VoterRegistry::new();
+        registry.register(0, alice.identity());
+        registry.register(1, bob.identity());
+
+        let voters = VoterSet::new();
+        let mut polling_station_state = VotingMachineState::new(2, voters.root());
+        polling_station_state.registry_root = Some(registry.root());
+        let mut polling_station = PollingStation::new(polling_station_state, voters)
+            .unwrap()
+            .with_registry(registry.clone())
+            .unwrap();
+
+        let init_state = polling_station.init().unwrap().get_state().unwrap();
+        assert_eq!(init_state.registry_root, Some(registry.root()));
+
+        let forged = Ballot {
+            voter: 0,
+            vote: Vote::Choice(0),
+            passphrase: Some(bob.clone()),
+        };
+        let unsigned = Ballot {
+            voter: 1,
+            vote: Vote::Choice(0),
+            passphrase: None,
+        };
+        let unregistered = Ballot {
+            voter: 2,
+            vote: Vote::Choice(0),
+            passphrase: Some(Passphrase { pass: [3; 32] }),
+        };
+        for ballot in [&forged, &unsigned, &unregistered] {
+            let commit = polling_station
+                .submit(ballot)
+                .unwrap()
+                .verify_and_get_commit()
+                .unwrap();
+            assert!(!commit.vote_counted);
+        }
+
+        let batch_commit = polling_station
+            .submit_batch(&[
+                Ballot {
+                    voter: 0,
+                    vote: Vote::Choice(1),
+                    passphrase: Some(alice),
+                },
+                Ballot {
+                    voter: 1,
+                    vote: Vote::Choice(1),
+                    passphrase: Some(bob),
+                },
+            ])
+            .unwrap()
+            .verify_and_get_commit()
+            .unwrap();
+        assert_eq!(batch_commit.vote_counted, vec![true, true]);
+
+        let close_commit = polling_station
+            .freeze()
+            .unwrap()
+            .verify_and_get_commit()
+            .unwrap();
+        assert_eq!(close_commit.tally, vec![0, 2]);
+    }
+
+    #[test]
+    fn poll_window() {
+        let voters = VoterSet::new();
+        let mut polling_station_state = VotingMachineState::new(2, voters.root());
+        polling_station_state.window = Some(PollWindow::new(1_000, 2_000));
+        let mut polling_station = PollingStation::new(polling_station_state, voters).unwrap();
+
+        let init_state = polling_station.init().unwrap().get_state().unwrap();
+        assert_eq!(init_state.window, Some(PollWindow::new(1_000, 2_000)));
+
+        let ballot = Ballot {
+            voter: 0,
+            vote: Vote::Choice(1),
+            passphrase: None,
+        };
+        for (time, counted) in [(999, false), (2_000, false), (1_500, true)] {
+            let commit = polling_station
+                .submit_at(&ballot, time)
+                .unwrap()
+                .verify_and_get_commit()
+                .unwrap();
+            assert_eq!(commit.time, time);
+            assert_eq!(commit.vote_counted, counted);
+        }
+        assert_eq!(polling_station.state.tally, vec![0, 1]);
+    }
+
+    #[test]
+    fn runoff_round() {
+        let voters = VoterSet::new();
+        let polling_station_state = VotingMachineState::new(2, voters.root());
+        let mut polling_station = PollingStation::new(polling_station_state, voters).unwrap();
+
+        let ballot = Ballot {
+            voter: 0,
+            vote: Vote::Choice(0),
+            passphrase: None,
+        };
+        let mut auditor = ElectionAuditor::new();
+        auditor.push(polling_station.init().unwrap());
+        auditor.push(polling_station.submit(&ballot).unwrap());
+        let first_freeze = polling_station.freeze().unwrap();
+        let frozen_state = first_freeze.get_commit().unwrap().new_state;
+        auditor.push(first_freeze);
+
+        let round_msg = polling_station.new_round(None).unwrap();
+        let round_commit = round_msg.verify_and_get_commit().unwrap();
+        assert_eq!(round_commit.old_state, frozen_state);
+        assert_eq!(round_commit.round, 1);
+        assert_eq!(round_commit.tally, vec![0, 0]);
+        assert_eq!(round_commit.voter_root, VoterSet::new().root());
+        auditor.push(round_msg);
+
+        // The same voter may vote again in the new round.
+        let ballot = Ballot {
+            vote: Vote::Choice(1),
+            ..ballot
+        };
+        let commit = polling_station
+            .submit(&ballot)
+            .unwrap()
+            .verify_and_get_commit()
+            .unwrap();
+        assert!(commit.vote_counted);
+        auditor.push(polling_station.freeze().unwrap());
+
+        let report = auditor.audit().unwrap();
+        assert_eq!(report.round, 1);
+        assert_eq!(report.tally, vec![0, 1]);
+        assert_eq!(report.round_tallies, vec![vec![1, 0], vec![0, 1]]);
+        assert_eq!(
+            report
+                .ballots
+                .iter()
+                .map(|ballot| ballot.round)
+                .collect::<Vec<_>>(),
+            vec![0, 1]
+        );
+    }
+
+    #[test]
+    fn resume() {
+        let path =
+            std::env::temp_dir().join(format!("voting-machine-resume-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        let ballot = |voter, option| Ballot {
+            voter,
+            vote: Vote::Choice(option),
+            passphrase: None,
+        };
+
+        let voters = VoterSet::new();
+        let polling_station_state = VotingMachineState::new(2, voters.root());
+        let mut polling_station = PollingStation::new(polling_station_state, voters)
+            .unwrap()
+            .with_storage(&path)
+            .unwrap();
+        polling_station.init().unwrap();
+        polling_station.submit(&ballot(0, 1)).unwrap();
+        polling_station
+            .submit_batch(&[ballot(1, 0), ballot(2, 1)])
+            .unwrap();
+        drop(polling_station);
+
+        let mut polling_station = PollingStation::open(&path).unwrap();
+        assert!(polling_station.voters.has_voted(2));
+        // The reopened station still remembers who has voted.
+        let commit = polling_station
+            .submit(&ballot(1, 1))
+            .unwrap()
+            .verify_and_get_commit()
+            .unwrap();
+        assert!(!commit.vote_counted);
+        polling_station.submit(&ballot(3, 1)).unwrap();
+        let close_commit = polling_station
+            .freeze()
+            .unwrap()
+            .verify_and_get_commit()
+            .unwrap();
+        assert_eq!(close_commit.tally, vec![1, 3]);
+        drop(polling_station);
+
+        let store = Store::open(&path).unwrap();
+        let mut auditor = ElectionAuditor::new();
+        for msg in store.messages().unwrap() {
+            auditor.push(msg);
+        }
+        let report = auditor.audit().unwrap();
+        assert_eq!(report.final_state, close_commit.new_state);
+        assert_eq!(report.ballots_counted(), 4);
+        assert!(PollingStation::new(
+            VotingMachineState::new(2, VoterSet::new().root()),
+            VoterSet::new()
+        )
+        .unwrap()
+        .with_storage(&path)
+        .is_err());
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn mismatched_tables_are_rejected() {
+        let voters = VoterSet::new();
+        let mut other_voters = VoterSet::new();
+        other_voters.insert(3);
+        let state = VotingMachineState::new(2, voters.root());
+        match PollingStation::new(state.clone(), other_voters).unwrap_err() {
+            VotingMachineError::StateMismatch { expected, found } => {
+                assert_eq!(expected, voters.root());
+                assert_ne!(found, voters.root());
+            }
+            err => panic!("unexpected error: {}", err),
+        }
+
+        let station = PollingStation::new(state.clone(), voters.clone()).unwrap();
+        match station.with_registry(VoterRegistry::new()).unwrap_err() {
+            VotingMachineError::NotCommitted { name } => assert_eq!(name, "voter registry"),
+            err => panic!("unexpected error: {}", err),
+        }
+
+        let mut weighted_state = state;
+        weighted_state.weight_root = Some(WeightTable::new().root());
+        let mut station = PollingStation::new(weighted_state, voters).unwrap();
+        let ballot = Ballot {
+            voter: 0,
+            vote: Vote::Choice(0),
+            passphrase: None,
+        };
+        assert!(matches!(
+            station.submit_batch(&[ballot]),
+            Err(VotingMachineError::NotCommitted {
+                name: "weight table"
+            })
+        ));
+    }
+
+    #[test]
+    fn errors() {
+        let voters = VoterSet::new();
+        let polling_station_state = VotingMachineState::new(2, voters.root());
+        let polling_station = PollingStation::new(polling_station_state, voters).unwrap();
+        let init_msg = polling_station.init().unwrap();
+
+        let msg = SubmitBallotMessage {
+            receipt: init_msg.receipt.clone(),
+        };
+        match msg.verify_and_get_commit().unwrap_err() {
+            VotingMachineError::WrongMethod { expected, found } => {
+                assert_eq!(expected, "submit");
+                assert_eq!(found, "init");
+            }
+            err => panic!("unexpected error: {}", err),
+        }
+
+        let mut receipt = init_msg.receipt.clone();
+        receipt.journal.truncate(1);
+        let msg = InitMessage { receipt };
+        assert!(matches!(
+            msg.get_state(),
+            Err(VotingMachineError::Decode { .. })
+        ));
+        assert!(matches!(
+            msg.verify_and_get_commit(),
+            Err(VotingMachineError::InvalidReceipt { .. })
+        ));
     }
 }
@@ -0,0 +1,407 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::{bail, Result};
+use clap::{value_parser, Arg, ArgMatches, Command};
+use log::LevelFilter;
+use voting_machine::{AuditedVote, ElectionAuditor, ElectionMessage, PollingStation, TallyMessage};
+use voting_machine_core::{
+    Ballot, DecisionRule, Outcome, PollWindow, Vote, VoterSet, VotingMachineState,
+};
+
+fn station_arg() -> Arg<'static> {
+    Arg::new("station")
+        .help("Directory holding the polling station's state and receipt log")
+        .required(true)
+        .value_parser(value_parser!(PathBuf))
+}
+
+fn out_arg() -> Arg<'static> {
+    Arg::new("out")
+        .help("File to write the receipt to")
+        .long("out")
+        .short('o')
+        .required(true)
+        .takes_value(true)
+        .value_parser(value_parser!(PathBuf))
+}
+
+fn opens_arg() -> Arg<'static> {
+    Arg::new("opens")
+        .help("Time polls open, in seconds since the Unix epoch")
+        .long("opens")
+        .takes_value(true)
+        .requires("closes")
+        .value_parser(value_parser!(u64))
+}
+
+fn closes_arg() -> Arg<'static> {
+    Arg::new("closes")
+        .help("Time polls close, in seconds since the Unix epoch")
+        .long("closes")
+        .takes_value(true)
+        .requires("opens")
+        .value_parser(value_parser!(u64))
+}
+
+/// Parse a vote given as a single option, or as a comma-separated ranking of options.
+fn parse_vote(vote: &str) -> Result<Vote> {
+    let options = vote
+        .split(',')
+        .map(|option| option.trim().parse())
+        .collect::<Result<Vec<u32>, _>>()?;
+    match options.as_slice() {
+        [option] => Ok(Vote::Choice(*option)),
+        _ => Ok(Vote::Ranked(options)),
+    }
+}
+
+/// Parse a majority threshold given as a fraction, such as `1/2` or `2/3`.
+fn parse_majority(majority: &str) -> Result<(u32, u32)> {
+    let (numerator, denominator) = match majority.split_once('/') {
+        Some(fraction) => fraction,
+        None => bail!("majority must be a fraction such as 2/3"),
+    };
+    let (numerator, denominator) = (numerator.trim().parse()?, denominator.trim().parse()?);
+    if numerator >= denominator {
+        bail!("majority must be less than one");
+    }
+    Ok((numerator, denominator))
+}
+
+/// Read the optional `--motion`, `--quorum` and `--majority` decision rule.
+fn parse_decision_rule(matches: &ArgMatches) -> Result<Option<DecisionRule>> {
+    let option = match matches.get_one::<u32>("motion") {
+        Some(option) => *option,
+        None => return Ok(None),
+    };
+    let quorum = matches.get_one::<u32>("quorum").copied().unwrap_or(0);
+    let (numerator, denominator) = match matches.get_one::<String>("majority") {
+        Some(majority) => parse_majority(majority)?,
+        None => (1, 2),
+    };
+    Ok(Some(DecisionRule::new(
+        option,
+        quorum,
+        numerator,
+        denominator,
+    )))
+}
+
+fn print_outcome(outcome: Option<Outcome>) {
+    match outcome {
+        Some(Outcome::Passed) => println!("Outcome: passed"),
+        Some(Outcome::Failed) => println!("Outcome: failed"),
+        Some(Outcome::NoQuorum) => println!("Outcome: no quorum"),
+        None => {}
+    }
+}
+
+/// Read the optional `--opens` and `--closes` times of a poll window.
+fn parse_window(matches: &ArgMatches) -> Result<Option<PollWindow>> {
+    match (
+        matches.get_one::<u64>("opens"),
+        matches.get_one::<u64>("closes"),
+    ) {
+        (Some(opens), Some(closes)) => {
+            if opens >= closes {
+                bail!("polls must open before they close");
+            }
+            Ok(Some(PollWindow::new(*opens, *closes)))
+        }
+        _ => Ok(None),
+    }
+}
+
+fn write_receipt(matches: &ArgMatches, bytes: Vec<u8>) -> Result<()> {
+    let path = matches.get_one::<PathBuf>("out").unwrap();
+    fs::write(path, bytes)?;
+    println!("Wrote receipt to {}", path.display());
+    Ok(())
+}
+
+fn init(matches: &ArgMatches) -> Result<()> {
+    let station = matches.get_one::<PathBuf>("station").unwrap();
+    let options = *matches.get_one::<u32>("options").unwrap();
+    let voters = VoterSet::new();
+    let mut state = VotingMachineState::new(options, voters.root());
+    state.window = parse_window(matches)?;
+    state.decision_rule = parse_decision_rule(matches)?;
+    let polling_station = PollingStation::new(state, voters)?.with_storage(station)?;
+    let msg = polling_station.init()?;
+    let commit = msg.verify_and_get_commit()?;
+    println!("Initialized election with {} options", options);
+    if let Some(window) = commit.window {
+        println!("Polls open from {} until {}", window.opens, window.closes);
+    }
+    if let Some(rule) = commit.decision_rule {
+        println!(
+            "Motion for option {} needs a quorum of {} and more than {}/{} of the votes",
+            rule.option, rule.quorum, rule.numerator, rule.denominator
+        );
+    }
+    println!("State: {:?}", commit.state);
+    write_receipt(matches, ElectionMessage::from(msg).to_bytes()?)
+}
+
+fn submit(matches: &ArgMatches) -> Result<()> {
+    let station = matches.get_one::<PathBuf>("station").unwrap();
+    let ballot = Ballot {
+        voter: *matches.get_one::<u32>("voter").unwrap(),
+        vote: parse_vote(matches.get_one::<String>("vote").unwrap())?,
+        passphrase: None,
+    };
+    let mut polling_station = PollingStation::open(station)?;
+    let msg = polling_station.submit(&ballot)?;
+    let commit = msg.verify_and_get_commit()?;
+    if commit.vote_counted {
+        println!("Ballot for voter {} was counted", commit.voter);
+    } else {
+        println!("Ballot for voter {} was NOT counted", commit.voter);
+    }
+    write_receipt(matches, ElectionMessage::from(msg).to_bytes()?)
+}
+
+fn freeze(matches: &ArgMatches) -> Result<()> {
+    let station = matches.get_one::<PathBuf>("station").unwrap();
+    let mut polling_station = PollingStation::open(station)?;
+    let msg = polling_station.freeze()?;
+    let commit = msg.verify_and_get_commit()?;
+    println!("Polls closed");
+    println!("Tally: {:?}", commit.tally);
+    print_outcome(commit.outcome);
+    write_receipt(matches, ElectionMessage::from(msg).to_bytes()?)
+}
+
+fn new_round(matches: &ArgMatches) -> Result<()> {
+    let station = matches.get_one::<PathBuf>("station").unwrap();
+    let mut polling_station = PollingStation::open(station)?;
+    let msg = polling_station.new_round(parse_window(matches)?)?;
+    let commit = msg.verify_and_get_commit()?;
+    println!("Started round {}", commit.round + 1);
+    if let Some(window) = commit.window {
+        println!("Polls open from {} until {}", window.opens, window.closes);
+    }
+    write_receipt(matches, ElectionMessage::from(msg).to_bytes()?)
+}
+
+fn tally(matches: &ArgMatches) -> Result<()> {
+    let station = matches.get_one::<PathBuf>("station").unwrap();
+    let polling_station = PollingStation::open(station)?;
+    let msg = polling_station.tally()?;
+    let commit = msg.verify_and_get_commit()?;
+    for (round, counts) in commit.rounds.iter().enumerate() {
+        println!("Round {}: {:?}", round + 1, counts);
+    }
+    match commit.winner {
+        Some(winner) => println!("Winner: option {}", winner),
+        None => println!("No winner"),
+    }
+    write_receipt(matches, msg.to_bytes()?)
+}
+
+fn verify(matches: &ArgMatches) -> Result<()> {
+    let mut auditor = ElectionAuditor::new();
+    for path in matches.get_many::<PathBuf>("receipts").unwrap() {
+        auditor.push(ElectionMessage::from_bytes(&fs::read(path)?)?);
+    }
+    let report = auditor.audit()?;
+    for ballot in &report.ballots {
+        let counted = if ballot.vote_counted {
+            "counted"
+        } else {
+            "not counted"
+        };
+        match &ballot.vote {
+            AuditedVote::Public {
+                voter,
+                vote,
+                weight: 1,
+            } => println!("Voter {}: {:?} ({})", voter, vote, counted),
+            AuditedVote::Public {
+                voter,
+                vote,
+                weight,
+            } => println!(
+                "Voter {}: {:?} with weight {} ({})",
+                voter, vote, weight, counted
+            ),
+            AuditedVote::Secret { nullifier } => {
+                println!("Secret ballot {:?} ({})", nullifier, counted)
+            }
+        }
+    }
+    println!("Ballots counted: {}", report.ballots_counted());
+    if report.round_tallies.len() > 1 {
+        for (round, tally) in report.round_tallies.iter().enumerate() {
+            println!("Round {} tally: {:?}", round + 1, tally);
+        }
+    }
+    println!("Tally: {:?}", report.tally);
+    print_outcome(report.outcome);
+
+    if let Some(path) = matches.get_one::<PathBuf>("tally") {
+        let commit = TallyMessage::from_bytes(&fs::read(path)?)?.verify_and_get_commit()?;
+        if commit.state != report.final_state {
+            bail!("tally receipt is for a different election state");
+        }
+        match commit.winner {
+            Some(winner) => println!("Winner: option {}", winner),
+            None => println!("No winner"),
+        }
+    }
+    println!("Election receipts are valid");
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    env_logger::builder().filter_level(LevelFilter::Warn).init();
+
+    let matches = Command::new("voting-machine")
+        .version("0.12.0")
+        .author("Risc0, Inc.")
+        .about("Run and audit an election with Risc0")
+        .subcommand_required(true)
+        .subcommand(
+            Command::new("init")
+                .about("Start a new election in a polling station directory")
+                .arg(station_arg())
+                .arg(
+                    Arg::new("options")
+                        .help("Number of options on the ballot")
+                        .long("options")
+                        .required(true)
+                        .takes_value(true)
+                        .value_parser(value_parser!(u32)),
+                )
+                .arg(opens_arg())
+                .arg(closes_arg())
+                .arg(
+                    Arg::new("motion")
+                        .help("Option that votes for the motion are cast for")
+                        .long("motion")
+                        .takes_value(true)
+                        .value_parser(value_parser!(u32)),
+                )
+                .arg(
+                    Arg::new("quorum")
+                        .help("Least number of votes for the outcome to stand")
+                        .long("quorum")
+                        .takes_value(true)
+                        .requires("motion")
+                        .value_parser(value_parser!(u32)),
+                )
+                .arg(
+                    Arg::new("majority")
+                        .help("Share of the votes the motion must exceed to pass [default: 1/2]")
+                        .long("majority")
+                        .takes_value(true)
+                        .requires("motion"),
+                )
+                .arg(out_arg()),
+        )
+        .subcommand(
+            Command::new("submit")
+                .about("Submit a ballot")
+                .arg(station_arg())
+                .arg(
+                    Arg::new("voter")
+                        .long("voter")
+                        .required(true)
+                        .takes_value(true)
+                        .value_parser(value_parser!(u32)),
+                )
+                .arg(
+                    Arg::new("vote")
+                        .help("Option to vote for, or a comma-separated ranking of options")
+                        .long("vote")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(out_arg()),
+        )
+        .subcommand(
+            Command::new("freeze")
+                .about("Close the polls and reveal the tally")
+                .arg(station_arg())
+                .arg(out_arg()),
+        )
+        .subcommand(
+            Command::new("new-round")
+                .about("Start another round of voting in a frozen election")
+                .arg(station_arg())
+                .arg(opens_arg())
+                .arg(closes_arg())
+                .arg(out_arg()),
+        )
+        .subcommand(
+            Command::new("tally")
+                .about("Run an instant-runoff count over a frozen election")
+                .arg(station_arg())
+                .arg(out_arg()),
+        )
+        .subcommand(
+            Command::new("verify")
+                .about("Audit the published receipts of an election, from init to the last freeze")
+                .arg(
+                    Arg::new("receipts")
+                        .help("Receipt files, in the order they were published")
+                        .required(true)
+                        .multiple_values(true)
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .arg(
+                    Arg::new("tally")
+                        .help("Tally receipt to check against the election")
+                        .long("tally")
+                        .takes_value(true)
+                        .value_parser(value_parser!(PathBuf)),
+                ),
+        )
+        .get_matches();
+
+    match matches.subcommand() {
+        Some(("init", matches)) => init(matches),
+        Some(("submit", matches)) => submit(matches),
+        Some(("freeze", matches)) => freeze(matches),
+        Some(("new-round", matches)) => new_round(matches),
+        Some(("tally", matches)) => tally(matches),
+        Some(("verify", matches)) => verify(matches),
+        _ => unreachable!(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use voting_machine_core::Vote;
+
+    use crate::{parse_majority, parse_vote};
+
+    #[test]
+    fn votes_parse_as_choice_or_ranking() {
+        assert_eq!(parse_vote("1").unwrap(), Vote::Choice(1));
+        assert_eq!(parse_vote("2, 0,1").unwrap(), Vote::Ranked(vec![2, 0, 1]));
+        assert!(parse_vote("1,x").is_err());
+    }
+
+    #[test]
+    fn majority_parses_as_fraction() {
+        assert_eq!(parse_majority("2/3").unwrap(), (2, 3));
+        assert!(parse_majority("3/2").is_err());
+        assert!(parse_majority("0.5").is_err());
+    }
+}
@@ -0,0 +1,312 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+use risc0_zkp::core::sha::Digest;
+use risc0_zkvm::Receipt;
+use serde::{Deserialize, Serialize};
+use voting_machine_core::{VoterRegistry, VoterSet, VotingMachineState, WeightTable};
+
+use crate::audit::{ElectionMessage, MessageKind};
+use crate::check_root;
+use crate::envelope::Envelope;
+use crate::error::{Result, VotingMachineError};
+
+const STATE_FILE: &str = "state";
+const NEXT_STATE_FILE: &str = "state.next";
+const LOG_FILE: &str = "receipts.log";
+
+/// Everything a polling station needs to carry on from where it left off.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Snapshot {
+    pub state: VotingMachineState,
+    pub voters: VoterSet,
+    pub registry: Option<VoterRegistry>,
+    pub weights: Option<WeightTable>,
+}
+
+impl Snapshot {
+    /// Check that the voter set, registry and weight table are the ones the state commits to.
+    pub fn check(&self) -> Result<()> {
+        check_root(
+            "voter set",
+            Some(&self.state.voter_root),
+            self.voters.root(),
+        )?;
+        check_table(
+            "voter registry",
+            self.state.registry_root.as_ref(),
+            self.registry.as_ref().map(VoterRegistry::root),
+        )?;
+        check_table(
+            "weight table",
+            self.state.weight_root.as_ref(),
+            self.weights.as_ref().map(WeightTable::root),
+        )
+    }
+}
+
+fn check_table(name: &'static str, committed: Option<&Digest>, root: Option<Digest>) -> Result<()> {
+    match (committed, root) {
+        (_, Some(root)) => check_root(name, committed, root),
+        (Some(_), None) => Err(VotingMachineError::storage(format!(
+            "saved state is missing its {}",
+            name
+        ))),
+        (None, None) => Ok(()),
+    }
+}
+
+/// On-disk storage for a polling station: a snapshot of its current state, and an append-only
+/// log of every message it has published.
+///
+/// Each message is recorded by first writing the snapshot it leads to as `state.next`, then
+/// appending the message to the log, and finally renaming `state.next` over `state`. A crash at
+/// any point leaves either the old or the new snapshot matching the end of the log, which
+/// [Store::load_snapshot] uses to pick the right one.
+#[derive(Debug)]
+pub struct Store {
+    dir: PathBuf,
+}
+
+impl Store {
+    /// Start a new store in `dir`, creating the directory if needed. Fails if `dir` already holds
+    /// a receipt log.
+    pub fn create(dir: impl AsRef<Path>, snapshot: &Snapshot) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        if dir.join(LOG_FILE).exists() {
+            return Err(VotingMachineError::storage(format!(
+                "{} already holds a polling station",
+                dir.display()
+            )));
+        }
+        let store = Store { dir };
+        store.write_snapshot(NEXT_STATE_FILE, snapshot)?;
+        File::create(store.dir.join(LOG_FILE))?.sync_all()?;
+        fs::rename(store.dir.join(NEXT_STATE_FILE), store.dir.join(STATE_FILE))?;
+        Ok(store)
+    }
+
+    /// Open an existing store in `dir`.
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        if !dir.join(LOG_FILE).exists() {
+            return Err(VotingMachineError::storage(format!(
+                "{} does not hold a polling station",
+                dir.display()
+            )));
+        }
+        Ok(Store { dir })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Record a published message along with the snapshot of the station after it.
+    pub fn append(&self, kind: MessageKind, receipt: &Receipt, snapshot: &Snapshot) -> Result<()> {
+        self.write_snapshot(NEXT_STATE_FILE, snapshot)?;
+        let entry = Envelope::new(kind.method(), receipt)?.to_bytes()?;
+        let mut log = OpenOptions::new()
+            .append(true)
+            .open(self.dir.join(LOG_FILE))?;
+        log.write_all(&(entry.len() as u32).to_le_bytes())?;
+        log.write_all(&entry)?;
+        log.sync_all()?;
+        fs::rename(self.dir.join(NEXT_STATE_FILE), self.dir.join(STATE_FILE))?;
+        Ok(())
+    }
+
+    /// Read back every message in the log, in the order it was published.
+    ///
+    /// A partly written entry at the end of the log is left over from a crash while appending,
+    /// and is cut off.
+    pub fn messages(&self) -> Result<Vec<ElectionMessage>> {
+        let path = self.dir.join(LOG_FILE);
+        let contents = fs::read(&path)?;
+        let mut messages = Vec::new();
+        let mut offset = 0;
+        while offset < contents.len() {
+            let entry = contents.get(offset + 4..).and_then(|rest| {
+                let len = u32::from_le_bytes(contents[offset..offset + 4].try_into().unwrap());
+                rest.get(..len as usize)
+            });
+            let entry = match entry {
+                Some(entry) => entry,
+                None => {
+                    log::warn!("truncating partial entry at the end of {}", path.display());
+                    OpenOptions::new()
+                        .write(true)
+                        .open(&path)?
+                        .set_len(offset as u64)?;
+                    break;
+                }
+            };
+            offset += 4 + entry.len();
+            messages.push(ElectionMessage::from_bytes(entry)?);
+        }
+        Ok(messages)
+    }
+
+    /// Load the snapshot whose state has the given digest, which should be the state at the end
+    /// of the log, or `None` if the log is empty.
+    ///
+    /// Fails with [VotingMachineError::StateMismatch] if the snapshot's state, voter set, registry
+    /// or weight table does not match what it should be.
+    pub fn load_snapshot(&self, state: Option<&Digest>) -> Result<Snapshot> {
+        let snapshot = self.pick_snapshot(state)?;
+        snapshot.check()?;
+        Ok(snapshot)
+    }
+
+    fn pick_snapshot(&self, state: Option<&Digest>) -> Result<Snapshot> {
+        let next = self.read_snapshot(NEXT_STATE_FILE)?;
+        let current = self.read_snapshot(STATE_FILE)?;
+        let promote_next = match (state, &next, &current) {
+            // The last append got as far as the log, so the new snapshot is the right one.
+            (Some(state), Some(next), _) => &next.state.digest() == state,
+            // A crash while creating the store leaves only the initial snapshot in `state.next`.
+            (None, Some(_), None) => true,
+            _ => false,
+        };
+        if promote_next {
+            fs::rename(self.dir.join(NEXT_STATE_FILE), self.dir.join(STATE_FILE))?;
+            return Ok(next.unwrap());
+        }
+        if next.is_some() {
+            fs::remove_file(self.dir.join(NEXT_STATE_FILE))?;
+        }
+        match (state, current) {
+            (Some(state), Some(snapshot)) if &snapshot.state.digest() != state => {
+                Err(VotingMachineError::StateMismatch {
+                    expected: *state,
+                    found: snapshot.state.digest(),
+                })
+            }
+            (_, Some(snapshot)) => Ok(snapshot),
+            (_, None) => Err(VotingMachineError::storage(format!(
+                "{} is missing its saved state",
+                self.dir.display()
+            ))),
+        }
+    }
+
+    fn read_snapshot(&self, name: &str) -> Result<Option<Snapshot>> {
+        match fs::read(self.dir.join(name)) {
+            Ok(bytes) => Ok(Some(bincode::deserialize(&bytes)?)),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn write_snapshot(&self, name: &str, snapshot: &Snapshot) -> Result<()> {
+        let mut file = File::create(self.dir.join(name))?;
+        file.write_all(&bincode::serialize(snapshot)?)?;
+        file.sync_all()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("voting-machine-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn snapshot(options: u32) -> Snapshot {
+        let voters = VoterSet::new();
+        Snapshot {
+            state: VotingMachineState::new(options, voters.root()),
+            voters,
+            registry: None,
+            weights: None,
+        }
+    }
+
+    #[test]
+    fn partial_log_entry_is_truncated() {
+        let dir = temp_dir("partial-log");
+        let store = Store::create(&dir, &snapshot(2)).unwrap();
+        store
+            .append(
+                MessageKind::Init,
+                &Receipt::new(&[1, 2], &[3]),
+                &snapshot(2),
+            )
+            .unwrap();
+        let len = fs::metadata(dir.join(LOG_FILE)).unwrap().len();
+        let mut log = OpenOptions::new()
+            .append(true)
+            .open(dir.join(LOG_FILE))
+            .unwrap();
+        log.write_all(&100u32.to_le_bytes()).unwrap();
+        log.write_all(&[0; 10]).unwrap();
+
+        let store = Store::open(&dir).unwrap();
+        let messages = store.messages().unwrap();
+        assert_eq!(messages.len(), 1);
+        match &messages[0] {
+            ElectionMessage::Init(msg) => assert_eq!(msg.receipt.journal, vec![1, 2]),
+            msg => panic!("unexpected {:?} message", msg.kind()),
+        }
+        assert_eq!(fs::metadata(dir.join(LOG_FILE)).unwrap().len(), len);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn interrupted_append_picks_matching_snapshot() {
+        let dir = temp_dir("interrupted-append");
+        let old = snapshot(2);
+        let new = snapshot(3);
+        let store = Store::create(&dir, &old).unwrap();
+        store.write_snapshot(NEXT_STATE_FILE, &new).unwrap();
+
+        // The log never saw the message leading to the new snapshot.
+        assert_eq!(store.load_snapshot(Some(&old.state.digest())).unwrap(), old);
+        assert!(!dir.join(NEXT_STATE_FILE).exists());
+
+        // The log did see it, but the new snapshot was never moved into place.
+        store.write_snapshot(NEXT_STATE_FILE, &new).unwrap();
+        assert_eq!(store.load_snapshot(Some(&new.state.digest())).unwrap(), new);
+        assert_eq!(store.read_snapshot(STATE_FILE).unwrap(), Some(new));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn mismatched_voters_are_rejected() {
+        let dir = temp_dir("mismatched-voters");
+        let mut saved = snapshot(2);
+        let store = Store::create(&dir, &saved).unwrap();
+        saved.voters.insert(7);
+        store.write_snapshot(STATE_FILE, &saved).unwrap();
+
+        match store.load_snapshot(Some(&saved.state.digest())) {
+            Err(VotingMachineError::StateMismatch { expected, found }) => {
+                assert_eq!(expected, saved.state.voter_root);
+                assert_eq!(found, saved.voters.root());
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -26,13 +26,17 @@ pub fn main() {
     let result = params.process();
     env::write(&result);
     let polls_open = result.state.polls_open;
-    let voter_bitfield = result.state.voter_bitfield;
-    let count = result.state.count;
+    let voter_root = result.state.voter_root;
+    let tally = result.state.tally.clone();
+    let decision_rule = result.state.decision_rule;
+    let outcome = result.state.outcome();
     env::commit(&FreezeVotingMachineCommit {
         old_state: *sha::digest(&params.state),
         new_state: *sha::digest(&result.state),
         polls_open: polls_open,
-        voter_bitfield: voter_bitfield,
-        count: count,
+        voter_root: voter_root,
+        tally: tally,
+        decision_rule: decision_rule,
+        outcome: outcome,
     });
 }
@@ -25,7 +25,13 @@ pub fn main() {
     let state: VotingMachineState = env::read();
     env::commit(&InitializeVotingMachineCommit {
         polls_open: state.polls_open,
-        voter_bitfield: state.voter_bitfield,
+        voter_root: state.voter_root,
+        tally: state.tally.clone(),
+        secret_ballot: state.is_secret_ballot(),
+        registry_root: state.registry_root,
+        weight_root: state.weight_root,
+        window: state.window,
+        decision_rule: state.decision_rule,
         state: *sha::digest(&state),
     });
 }
@@ -0,0 +1,41 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![no_main]
+#![no_std]
+
+use risc0_zkvm::guest::{env, sha};
+
+use voting_machine_core::{NewRoundCommit, NewRoundParams};
+
+risc0_zkvm::guest::entry!(main);
+
+pub fn main() {
+    let params: NewRoundParams = env::read();
+    let result = params.process();
+    env::write(&result);
+    let state = &result.state;
+    env::commit(&NewRoundCommit {
+        old_state: *sha::digest(&params.state),
+        new_state: *sha::digest(state),
+        round: state.round,
+        polls_open: state.polls_open,
+        voter_root: state.voter_root,
+        tally: state.tally.clone(),
+        registry_root: state.registry_root,
+        weight_root: state.weight_root,
+        window: state.window,
+        decision_rule: state.decision_rule,
+    });
+}
@@ -26,17 +26,20 @@ pub fn main() {
     let result = params.process();
     env::write(&result);
     let polls_open = result.state.polls_open;
-    let voter_bitfield = result.state.voter_bitfield;
+    let voter_root = result.state.voter_root;
     let voter = params.ballot.voter;
-    let vote_yes = params.ballot.vote_yes;
+    let vote = params.ballot.vote.clone();
+    let weight = params.state.weight(voter, params.weight_proof.as_ref());
     let vote_counted = result.vote_counted;
     env::commit(&SubmitBallotCommit {
         old_state: *sha::digest(&params.state),
         new_state: *sha::digest(&result.state),
         polls_open: polls_open,
-        voter_bitfield: voter_bitfield,
+        voter_root: voter_root,
         voter: voter,
-        vote_yes: vote_yes,
+        vote: vote,
+        weight: weight,
+        time: params.time,
         vote_counted: vote_counted,
     });
 }
@@ -0,0 +1,50 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![no_main]
+#![no_std]
+
+use risc0_zkvm::guest::{env, sha};
+
+use voting_machine_core::{SubmitBatchCommit, SubmitBatchParams};
+
+risc0_zkvm::guest::entry!(main);
+
+pub fn main() {
+    let params: SubmitBatchParams = env::read();
+    let result = params.process();
+    env::write(&result);
+    let polls_open = result.state.polls_open;
+    let voter_root = result.state.voter_root;
+    let voters = params.ballots.iter().map(|ballot| ballot.voter).collect();
+    let votes = params.ballots.iter().map(|ballot| ballot.vote.clone()).collect();
+    let weights = params
+        .ballots
+        .iter()
+        .zip(params.weight_proofs.iter())
+        .map(|(ballot, weight_proof)| params.state.weight(ballot.voter, weight_proof.as_ref()))
+        .collect();
+    let vote_counted = result.vote_counted.clone();
+    env::commit(&SubmitBatchCommit {
+        old_state: *sha::digest(&params.state),
+        new_state: *sha::digest(&result.state),
+        polls_open: polls_open,
+        voter_root: voter_root,
+        voters: voters,
+        votes: votes,
+        weights: weights,
+        time: params.time,
+        vote_counted: vote_counted,
+    });
+}
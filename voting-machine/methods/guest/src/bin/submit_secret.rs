@@ -0,0 +1,36 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![no_main]
+#![no_std]
+
+use risc0_zkvm::guest::{env, sha};
+
+use voting_machine_core::{SubmitSecretBallotCommit, SubmitSecretBallotParams};
+
+risc0_zkvm::guest::entry!(main);
+
+pub fn main() {
+    let params: SubmitSecretBallotParams = env::read();
+    let result = params.process();
+    env::write(&result);
+    let vote_counted = result.vote_counted;
+    env::commit(&SubmitSecretBallotCommit {
+        old_state: *sha::digest(&params.state),
+        new_state: *sha::digest(&result.state),
+        nullifier: params.nullifier(),
+        time: params.time,
+        vote_counted: vote_counted,
+    });
+}